@@ -1,25 +1,440 @@
 use crate::prelude::*;
 
-const ROLLING_AVERAGE_WINDOW: u32 = 14;
-const ROLLING_AVERAGE_MINIMUM: usize = 10;
-const ROLLING_AVERAGE_DISCARD: usize = 4;
+/// The results of analysing a single numeric field.
+pub struct FieldReport {
+	pub field: String,
+	/// Data points within the last `max_timeline` days, which is all that
+	/// trends are looked for in.
+	pub points: usize,
+	pub trends: Vec<Trend>,
+	pub outliers: Vec<u32>,
+}
+
+/// A comparison of an output's values on days with some condition against
+/// days without it.
+pub struct Comparison {
+	pub with_count: usize,
+	pub without_count: usize,
+	pub with_mean: f64,
+	pub without_mean: f64,
+	/// Cohen's d; the difference in means in units of pooled standard deviation.
+	pub effect_size: f64,
+	/// Two-sided p-value from Welch's t-test.
+	pub p_value: f64,
+}
+
+/// How a numeric output differs between days on which a boolean input was
+/// true and days on which it was false.
+pub struct BooleanEffect {
+	pub input: String,
+	pub output: String,
+	pub comparison: Comparison,
+}
+
+/// How a numeric output differs on the days following a tag (or any tag in
+/// a namespace, such as `ate:`) compared to all other days.
+pub struct TagEffect {
+	pub tag: String,
+	pub output: String,
+	/// Days between the tag and the effect on the output.
+	pub lag: u32,
+	/// Days on which the tag was recorded.
+	pub support: usize,
+	/// The comparison at `lag`, with its p-value corrected for the number
+	/// of lags tried.
+	pub comparison: Comparison,
+}
 
-pub fn analyze(path: PathBuf) {
-	let dbh = DbHandler::new(path);
-	// let entries = dbh.get_entries().unwrap();
-	let fields = dbh.get_fields().unwrap(); // only output fields?
-	// let vlobf = calculate_lobf_from_entries(&entries);
+/// How an output changed when a state started or ended, found by fitting
+/// separate lines to the `trend_window` days either side of the change.
+pub struct StateEffect {
+	pub state: String,
+	pub output: String,
+	/// True if the change was the state starting, false if it was ending.
+	pub started: bool,
+	pub date: u32,
+	pub before: Trend,
+	pub after: Trend,
+	/// Jump in the output at the change, comparing both lines on that day.
+	pub level_shift: f64,
+	pub level_shift_se: f64,
+	pub level_shift_p: f64,
+	/// Change in slope per day from before to after.
+	pub slope_change: f64,
+	pub slope_change_se: f64,
+	pub slope_change_p: f64,
+}
+
+pub struct Report {
+	/// Data points needed before a field is analysed at all.
+	pub min_points: usize,
+	pub min_confidence: f64,
+	pub fields: Vec<FieldReport>,
+	pub boolean_effects: Vec<BooleanEffect>,
+	pub tag_effects: Vec<TagEffect>,
+	pub state_effects: Vec<StateEffect>,
+	/// The strongest lag for each pair of numeric input and output fields.
+	pub correlations: Vec<Correlation>,
+	/// Units and ranges of the numeric fields, for labelling values.
+	pub meta: HashMap<String, FieldMeta>,
+	/// Durations and times of day are analysed in minutes, but shown the
+	/// way they are entered.
+	pub data_types: HashMap<String, FieldType>,
+	/// Notes written on outlier days, which may explain them.
+	pub notes: HashMap<u32, String>,
+}
+
+pub fn analyze(config: &Config) -> Result<Report> {
+	let dbh = DbHandler::new(config.db_path());
+	// Deactivated fields are no longer recorded, but their history is still
+	// worth analysing.
+	let fields = dbh.get_all_fields()?;
+	let min_points = config.trend_rolling_window + 1;
+	let mut reports = Vec::new();
+
+	for field in fields.iter().filter(|f| f.data_type.is_numeric()) {
+		let values = dbh.get_numeric_values(&field.name)?;
+		// The range is only asked for once there is data, as it has none to
+		// report on an empty database.
+		let start = if values.is_empty() { 0 } else { analysis_window(&dbh, config).0 };
+		let points = values.iter().filter(|(day, _)| *day >= start).count();
+		let mut report = FieldReport {
+			field: field.name.clone(),
+			points,
+			trends: Vec::new(),
+			outliers: Vec::new(),
+		};
+		if points >= min_points {
+			report.trends = find_all_trends(&dbh, &field.name, config)?;
+			report.outliers = detect_outliers(&dbh, &field.name, config)?;
+		}
+		reports.push(report);
+	}
 
-	// let start_date = entries.first().unwrap().date.clone();
-	// let end_date = entries.last().unwrap().date.clone();
-	let mut all_trends = Vec::new();
+	let meta = fields.iter().map(|f| (f.name.clone(), f.meta.clone())).collect();
+	let data_types = fields.iter().map(|f| (f.name.clone(), f.data_type.clone())).collect();
+	let (fields, entries) = prepare_entries(&fields, dbh.get_entries()?);
+	let outliers: Vec<u32> = reports.iter().flat_map(|r| r.outliers.iter().copied()).collect();
+	let notes = entries.iter()
+		.map(|e| (e.date.to_julian_day() as u32, e))
+		.filter(|(day, _)| outliers.contains(day))
+		.filter_map(|(day, e)| Some((day, e.note.clone()?)))
+		.collect();
+	let boolean_effects = find_boolean_effects(&fields, &entries);
+	let tag_effects = find_tag_effects(&fields, &entries, config);
+	let state_effects = find_state_effects(&dbh, &fields, config)?;
+	let correlations = strongest_correlations(correlation_matrix(&fields, &entries, config.coincidence_window));
 
+	Ok(Report {
+		min_points,
+		min_confidence: config.min_confidence,
+		fields: reports,
+		boolean_effects,
+		tag_effects,
+		state_effects,
+		correlations,
+		meta,
+		data_types,
+		notes,
+	})
+}
+
+/// Readies entries for analysis. Each choice of a categorical field becomes
+/// a boolean field of its own, such as `workout=run`, which is true on days
+/// that choice was made and false on days another was. Times of day are
+/// moved to within twelve hours of their field's mean, so that bedtimes
+/// either side of midnight stay close.
+pub fn prepare_entries(fields: &[Field], mut entries: Vec<Entry>) -> (Vec<Field>, Vec<Entry>) {
+	let mut prepared = Vec::new();
 	for field in fields {
-		let trends = find_all_trends(&dbh, &field.name);
-		all_trends.append(&mut trends.unwrap());
-		// baseline.insert(field.name.clone(), trend);
+		match field.data_type {
+			FieldType::Categorical => {
+				for choice in &field.meta.choices {
+					let name = format!("{}={}", field.name, choice);
+					for entry in entries.iter_mut() {
+						if let Some(chosen) = entry.categorical_fields.get(&field.name) {
+							let flag = chosen == choice;
+							entry.boolean_fields.insert(name.clone(), flag);
+						}
+					}
+					prepared.push(Field {
+						name,
+						data_type: FieldType::Boolean,
+						meta: FieldMeta::default(),
+						..field.clone()
+					});
+				}
+			},
+			FieldType::TimeOfDay => {
+				let times: Vec<f64> = entries.iter().filter_map(|e| e.numeric_fields.get(&field.name).copied()).collect();
+				let centre = mean_time_of_day(&times);
+				for time in entries.iter_mut().filter_map(|e| e.numeric_fields.get_mut(&field.name)) {
+					*time = unwrap_time_of_day(*time, centre);
+				}
+				prepared.push(field.clone());
+			},
+			_ => prepared.push(field.clone()),
+		}
+	}
+
+	(prepared, entries)
+}
+
+/// Bonferroni correction for keeping the smallest of `tests` p-values, such
+/// as the best of several lags, so that trying more lags does not make a
+/// finding look more certain.
+fn correct_for_tests(p_value: f64, tests: usize) -> f64 {
+	(p_value * tests.max(1) as f64).min(1.0)
+}
+
+/// Keeps only the most significant lag for each input and output pair,
+/// ordered by significance. Its p-values are corrected for the number of
+/// lags that were tested for the pair.
+fn strongest_correlations(correlations: Vec<Correlation>) -> Vec<Correlation> {
+	let mut best: Vec<(Correlation, usize)> = Vec::new();
+	for c in correlations {
+		match best.iter_mut().find(|(b, _)| b.input == c.input && b.output == c.output) {
+			Some((b, tests)) => {
+				*tests += 1;
+				if c.pearson_p < b.pearson_p {
+					*b = c;
+				}
+			},
+			None => best.push((c, 1)),
+		}
+	}
+	let mut best: Vec<Correlation> = best.into_iter().map(|(mut c, tests)| {
+		c.pearson_p = correct_for_tests(c.pearson_p, tests);
+		c.spearman_p = correct_for_tests(c.spearman_p, tests);
+		c
+	}).collect();
+	best.sort_by(|a, b| a.pearson_p.partial_cmp(&b.pearson_p).unwrap());
+
+	best
+}
+
+/// Two-sided p-value for a trend's slope differing from zero. Trends only
+/// keep their residual spread, so the spread of dates is approximated by
+/// assuming the points are evenly spaced between the trend's ends.
+fn trend_p_value(trend: &Trend) -> f64 {
+	let n = trend.points as f64;
+	let span = trend.end.saturating_sub(trend.start) as f64;
+	if n <= 2.0 || span == 0.0 {
+		return 1.0
 	}
 
+	let sum_squares = n * (n + 1.0) * span * span / (12.0 * (n - 1.0));
+	let residual_se = trend.stddev * (n / (n - 2.0)).sqrt();
+	if residual_se == 0.0 {
+		return if trend.slope == 0.0 { 1.0 } else { 0.0 }
+	}
+
+	student_t_p_value(trend.slope / (residual_se / sum_squares.sqrt()), n - 2.0)
+}
+
+impl Report {
+	/// Turns the report's findings into recommendations, identified by type,
+	/// output and input. Lags are kept apart from the input, so a finding
+	/// keeps its identity when its strongest lag moves, and state changes
+	/// carry their date. Only the latest trend of each field is included.
+	pub fn recommendations(&self) -> Vec<Recommendation> {
+		let finding = |kind, output: &str, input: String, lag, p_value: f64| Recommendation {
+			id: 0,
+			kind,
+			output: output.to_string(),
+			input,
+			lag,
+			confidence: 1.0 - p_value,
+			hidden: false,
+			stale: false,
+		};
+		let mut findings = Vec::new();
+
+		for field in &self.fields {
+			if let Some(trend) = field.trends.last() {
+				findings.push(finding(RecommendationType::Trend, &field.field, String::from("time"), None, trend_p_value(trend)));
+			}
+		}
+		for c in &self.correlations {
+			findings.push(finding(RecommendationType::Correlation, &c.output, c.input.clone(), Some(c.lag), c.pearson_p));
+		}
+		for effect in &self.boolean_effects {
+			findings.push(finding(RecommendationType::Boolean, &effect.output, effect.input.clone(), None, effect.comparison.p_value));
+		}
+		for effect in &self.tag_effects {
+			findings.push(finding(RecommendationType::Tag, &effect.output, effect.tag.clone(), Some(effect.lag), effect.comparison.p_value));
+		}
+		for effect in &self.state_effects {
+			let input = format!(
+				"{} {} {}",
+				effect.state,
+				if effect.started { "started" } else { "ended" },
+				format_julian(effect.date),
+			);
+			let p_value = effect.level_shift_p.min(effect.slope_change_p);
+			findings.push(finding(RecommendationType::State, &effect.output, input, None, p_value));
+		}
+
+		findings
+	}
+
+	/// The unit of a field with a leading space, or nothing if it has none.
+	/// Durations and times of day change in minutes.
+	fn unit(&self, field: &str) -> String {
+		if matches!(self.data_types.get(field), Some(FieldType::Duration | FieldType::TimeOfDay)) {
+			return String::from(" minutes")
+		}
+		match self.meta.get(field).and_then(|m| m.unit.as_ref()) {
+			Some(unit) => format!(" {}", unit),
+			None => String::new(),
+		}
+	}
+
+	/// Formats a typical value of a field, such as a mean, with its unit.
+	fn value(&self, field: &str, value: f64) -> String {
+		match self.data_types.get(field) {
+			Some(data_type @ (FieldType::Duration | FieldType::TimeOfDay)) => data_type.format_value(value),
+			_ => format!("{:.2}{}", value, self.unit(field)),
+		}
+	}
+
+	pub fn print(&self) {
+		if self.fields.is_empty() {
+			println!("There are no numeric fields to analyse.");
+		}
+		for field in &self.fields {
+			match self.meta.get(&field.field).and_then(|m| m.label()) {
+				Some(label) => println!("\n{} [{}] ({} data points)", field.field, label, field.points),
+				None => println!("\n{} ({} data points)", field.field, field.points),
+			}
+			let unit = self.unit(&field.field);
+			if field.points < self.min_points {
+				println!("  Not enough data to analyse yet; at least {} data points are needed.", self.min_points);
+				continue
+			}
+
+			if field.trends.is_empty() {
+				println!("  No sustained trends found.");
+			} else {
+				println!("  Trends:");
+				for trend in &field.trends {
+					println!(
+						"    {} to {}: {:+.2}{}/day ({:+.2}{}/week), stddev {:.2}{}, {} data points",
+						format_julian(trend.start),
+						format_julian(trend.end),
+						trend.slope,
+						unit,
+						trend.slope * 7.0,
+						unit,
+						trend.stddev,
+						unit,
+						trend.points,
+					);
+				}
+			}
+
+			if field.outliers.is_empty() {
+				println!("  No outliers found.");
+			} else {
+				let dates: Vec<String> = field.outliers.iter().map(|d| format_julian(*d)).collect();
+				println!("  Outliers: {}", dates.join(", "));
+				for day in &field.outliers {
+					if let Some(note) = self.notes.get(day) {
+						println!("    {}: {}", format_julian(*day), note.lines().next().unwrap_or_default());
+					}
+				}
+			}
+		}
+
+		if !self.boolean_effects.is_empty() {
+			println!("\nBoolean inputs against numeric outputs, most significant first:");
+			for effect in &self.boolean_effects {
+				let c = &effect.comparison;
+				let marker = if 1.0 - c.p_value >= self.min_confidence { " *" } else { "" };
+				println!(
+					"  {} -> {}: {} on {} true days vs {} on {} false days, d = {:+.2}, p = {:.4}{}",
+					effect.input,
+					effect.output,
+					self.value(&effect.output, c.with_mean),
+					c.with_count,
+					self.value(&effect.output, c.without_mean),
+					c.without_count,
+					c.effect_size,
+					c.p_value,
+					marker,
+				);
+			}
+			println!("  (* clears the minimum confidence of {})", self.min_confidence);
+		}
+
+		if !self.tag_effects.is_empty() {
+			println!("\nTags affecting numeric outputs, most significant first:");
+			for effect in &self.tag_effects {
+				let c = &effect.comparison;
+				let when = match effect.lag {
+					0 => String::from("same day"),
+					1 => String::from("1 day later"),
+					lag => format!("{} days later", lag),
+				};
+				// Namespace effects are keyed by the namespace and its colon.
+				let tag = match effect.tag.strip_suffix(':') {
+					Some(namespace) => format!("any {}: tag", namespace),
+					None => effect.tag.clone(),
+				};
+				println!(
+					"  {} -> {} ({}): {} vs {} otherwise, d = {:+.2}, p = {:.4}, seen on {} days",
+					tag,
+					effect.output,
+					when,
+					self.value(&effect.output, c.with_mean),
+					self.value(&effect.output, c.without_mean),
+					c.effect_size,
+					c.p_value,
+					effect.support,
+				);
+			}
+		}
+
+		if !self.state_effects.is_empty() {
+			println!("\nChanges in outputs when states started or ended, most significant first:");
+			for effect in &self.state_effects {
+				println!(
+					"  {} {} on {}, {}: level {:+.2}{} ± {:.2} (p = {:.4}), slope {:+.3}{}/day ± {:.3} (p = {:.4}); {} points before, {} after",
+					effect.state,
+					if effect.started { "started" } else { "ended" },
+					format_julian(effect.date),
+					effect.output,
+					effect.level_shift,
+					self.unit(&effect.output),
+					effect.level_shift_se,
+					effect.level_shift_p,
+					effect.slope_change,
+					self.unit(&effect.output),
+					effect.slope_change_se,
+					effect.slope_change_p,
+					effect.before.points,
+					effect.after.points,
+				);
+			}
+		}
+
+		let significant: Vec<&Correlation> = self.correlations.iter().filter(|c| 1.0 - c.pearson_p >= self.min_confidence).collect();
+		if !significant.is_empty() {
+			println!("\nCorrelations clearing the minimum confidence of {}:", self.min_confidence);
+			for c in significant {
+				println!(
+					"  {} -> {} ({} day lag): r = {:+.3}, rho = {:+.3}, p = {:.4}, {} days",
+					c.input,
+					c.output,
+					c.lag,
+					c.pearson,
+					c.spearman,
+					c.pearson_p,
+					c.samples,
+				);
+			}
+		}
+	}
 }
 
 fn calculate_std_dev(entries: &Vec<(u32, f64)>, slope: f64, intercept: f64) -> f64 {
@@ -62,20 +477,28 @@ fn calculate_single_lobf(entries: &Vec<(u32, f64)>) -> (f64, f64) {
 	(slope, intercept)
 }
 
-fn find_all_trends(dbh: &DbHandler, field: &String) -> Result<Vec<Trend>> {
+/// The first and last days trends and outliers are looked for in: the last
+/// `max_timeline` days up to the latest entry.
+fn analysis_window(dbh: &DbHandler, config: &Config) -> (u32, u32) {
 	let (start, end) = dbh.get_range();
-	let mut date_vals = dbh.get_numeric_values_between_dates(&field, start, end).unwrap();
-	if date_vals.len() < 15 {
+	(start.max(end.saturating_sub(config.max_timeline)), end)
+}
+
+fn find_all_trends(dbh: &DbHandler, field: &str, config: &Config) -> Result<Vec<Trend>> {
+	let (start, end) = analysis_window(dbh, config);
+	let seed = config.trend_rolling_window;
+	let mut date_vals = dbh.get_numeric_values_between_dates(field, start, end)?;
+	if date_vals.len() <= seed {
 		return Err(anyhow!("Too little data to find trend for {}", field))
 	}
 	let mut trends = Vec::new();
 	// let mut old_trend = Trend::default();
-	while date_vals.len() > 14 {
-		let mut new_trend = find_trends(dbh, field, date_vals.first().unwrap().0, date_vals.get(13).unwrap().0).unwrap();
-		new_trend = extend_trend(&new_trend, field, dbh);
+	while date_vals.len() > seed {
+		let mut new_trend = find_trends(dbh, field, date_vals.first().unwrap().0, date_vals.get(seed - 1).unwrap().0)?;
+		new_trend = extend_trend(&new_trend, field, dbh, config)?;
 
-		date_vals = dbh.get_numeric_values_between_dates(&field, new_trend.end, end).unwrap();
-		if validate_trend(&new_trend) {
+		date_vals = dbh.get_numeric_values_between_dates(field, new_trend.end, end)?;
+		if validate_trend(&new_trend, config) {
 			trends.push(new_trend);
 		}
 	}
@@ -83,23 +506,23 @@ fn find_all_trends(dbh: &DbHandler, field: &String) -> Result<Vec<Trend>> {
 	Ok(trends)
 }
 
-fn validate_trend(trend: &Trend) -> bool {
-	let sufficient_length = trend.end - trend.start >= 30;
+fn validate_trend(trend: &Trend, config: &Config) -> bool {
+	let sufficient_length = trend.end - trend.start >= config.trend_window;
 	let significant_slope = trend.slope.abs() > 0.1;
 	let narrow_deviation = true; //How do we define this?
 
 	sufficient_length && significant_slope && narrow_deviation
 }
 
-fn extend_trend(trend: &Trend, field: &String, dbh: &DbHandler) -> Trend {
+fn extend_trend(trend: &Trend, field: &str, dbh: &DbHandler, config: &Config) -> Result<Trend> {
 	let (_, end) = dbh.get_range();
-	let date_vals = dbh.get_numeric_values_between_dates(&field, trend.end, end).unwrap();
+	let date_vals = dbh.get_numeric_values_between_dates(field, trend.end, end)?;
 	let mut trend = trend.clone();
 	// let mut accumulated_error = 0.0;
 	let mut total_outliers = 0.0;
 	let mut i = 0;
 	let mut correction = false;
-	let mut range = dbh.count_entries(field, trend.start, trend.end).unwrap() as f64;
+	let mut range = dbh.count_entries(field, trend.start, trend.end)? as f64;
 	let mut confidence = range / trend.stddev;
 	while i < date_vals.len() {
 		// let dv = date_vals.get(i);
@@ -114,8 +537,8 @@ fn extend_trend(trend: &Trend, field: &String, dbh: &DbHandler) -> Trend {
 		}
 		// if accumulated_error.abs() > expected_val && i > 10 {
 		if i > 6 && total_outliers / (i as f64 + range) > 0.05 {
-			let new_trend = find_trends(dbh, field, trend.start, *date).unwrap();
-			let new_range = dbh.count_entries(field, trend.start, new_trend.end).unwrap() as f64;
+			let new_trend = find_trends(dbh, field, trend.start, *date)?;
+			let new_range = dbh.count_entries(field, trend.start, new_trend.end)? as f64;
 			let new_confidence = new_range / new_trend.stddev;
 			let length_factor = new_range / (range * 1.1);
 			if new_confidence >= confidence * length_factor {
@@ -145,7 +568,7 @@ fn extend_trend(trend: &Trend, field: &String, dbh: &DbHandler) -> Trend {
 			}
 			// if (pos && accumulated_error < 0.0)
 			// || (!pos && accumulated_error > 0.0) {
-			if total_outliers / (i as f64 + 14.0) < 0.05 {
+			if total_outliers / (i as f64 + config.trend_rolling_window as f64) < 0.05 {
 				break
 			}
 	
@@ -155,29 +578,35 @@ fn extend_trend(trend: &Trend, field: &String, dbh: &DbHandler) -> Trend {
 
 	i = i.clamp(0, date_vals.len() - 1);
 	trend.end = date_vals.get(i).unwrap().0;
+	trend.points = dbh.count_entries(field, trend.start, trend.end)?;
 
-	trend
+	Ok(trend)
 }
 
-fn detect_outliers(dbh: &DbHandler, field: &String) -> Vec<u32> {
+fn detect_outliers(dbh: &DbHandler, field: &str, config: &Config) -> Result<Vec<u32>> {
 	let mut spikes = Vec::new();
-	let entries = dbh.get_numeric_values(field).unwrap();
+	let (start, end) = analysis_window(dbh, config);
+	let entries = dbh.get_numeric_values_between_dates(field, start, end)?;
+	// Points in the window before it is worth fitting a line at all, and
+	// before that line is trusted, both scaled from the spike window.
+	let discard = config.spike_window as usize * 2 / 7;
+	let minimum = config.spike_window as usize * 5 / 7;
 
 	let mut active: Vec<(u32, f64)> = Vec::new();
 	let mut recheck = Vec::new();
 
 	for (date, val) in entries.iter() {
 		while !active.is_empty() &&
-		active.first().unwrap().0 < date - ROLLING_AVERAGE_WINDOW {
+		active.first().unwrap().0 < date.saturating_sub(config.spike_window) {
 			active.remove(0);
 		}
 
-		if active.len() < ROLLING_AVERAGE_DISCARD {
+		if active.len() < discard {
 			active.push((*date, *val));
 			recheck.clear();
 			continue;
 		}
-		if active.len() < ROLLING_AVERAGE_MINIMUM {
+		if active.len() < minimum {
 			active.push((*date, *val));
 			recheck.push((*date, *val));
 			continue;
@@ -193,8 +622,7 @@ fn detect_outliers(dbh: &DbHandler, field: &String) -> Vec<u32> {
 			spikes.push(*date);
 		}
 
-		while !recheck.is_empty() {
-			let point = recheck.pop().unwrap();
+		while let Some(point) = recheck.pop() {
 			let time_delta = point.0 - active.first().unwrap().0;
 			let expected = intercept + slope * time_delta as f64;
 			if (point.1 - expected).abs() > 2.0 * stddev {
@@ -203,11 +631,11 @@ fn detect_outliers(dbh: &DbHandler, field: &String) -> Vec<u32> {
 		}
 	}
 
-	spikes
+	Ok(spikes)
 }
 
-fn _remove_outliers(range: &Vec<(i32, f64)>) -> Vec<(i32, f64)> {
-	let mut v = range.clone();
+fn _remove_outliers(range: &[(i32, f64)]) -> Vec<(i32, f64)> {
+	let mut v = range.to_vec();
     let max = range.iter().enumerate().max_by(|orig, new| orig.1.1.partial_cmp(&new.1.1).unwrap() ).unwrap().0;
 	v.remove(max);
     let min = range.iter().enumerate().min_by(|orig, new| orig.1.1.partial_cmp(&new.1.1).unwrap() ).unwrap().0;
@@ -215,8 +643,8 @@ fn _remove_outliers(range: &Vec<(i32, f64)>) -> Vec<(i32, f64)> {
 	v
 }
 
-fn find_trends(dbh: &DbHandler, field: &String, start_date: u32, end_date: u32) -> Result<Trend> {
-	let date_vals = dbh.get_numeric_values_between_dates(&field, start_date, end_date).unwrap();
+fn find_trends(dbh: &DbHandler, field: &str, start_date: u32, end_date: u32) -> Result<Trend> {
+	let date_vals = dbh.get_numeric_values_between_dates(field, start_date, end_date)?;
 	// let date_vals = remove_outliers(&date_vals);
 	// if date_vals.len() < 15 {
 	// 	return Err(anyhow!("Too little data to find trend for {}", field))
@@ -228,6 +656,7 @@ fn find_trends(dbh: &DbHandler, field: &String, start_date: u32, end_date: u32)
 		orig_val: intercept,
 		slope: lobf,
 		stddev: calculate_std_dev(&date_vals, lobf, intercept),
+		points: date_vals.len() as u32,
 	};
 	
 	Ok(new_trend)
@@ -244,10 +673,390 @@ struct WorkSet {
 	// value_deviance: f64,
 }
 
+/// Fewest days in either group before an effect is worth testing.
+const MIN_GROUP_SIZE: usize = 3;
+
+/// Compares every numeric output against every boolean input, splitting the
+/// output's values by whether the input was true that day.
+fn find_boolean_effects(fields: &[Field], entries: &[Entry]) -> Vec<BooleanEffect> {
+	let inputs = fields.iter().filter(|f| f.data_type == FieldType::Boolean && f.category != FieldCategory::Output);
+	let outputs: Vec<&Field> = fields.iter().filter(|f| f.data_type.is_numeric() && f.category != FieldCategory::Input).collect();
+	let mut effects = Vec::new();
+
+	for input in inputs {
+		for output in &outputs {
+			let mut on_true = Vec::new();
+			let mut on_false = Vec::new();
+			for entry in entries {
+				let flag = entry.boolean_fields.get(&input.name);
+				let value = entry.numeric_fields.get(&output.name);
+				match (flag, value) {
+					(Some(true), Some(value)) => on_true.push(*value),
+					(Some(false), Some(value)) => on_false.push(*value),
+					_ => (),
+				}
+			}
+
+			if let Some(comparison) = compare_groups(&on_true, &on_false) {
+				effects.push(BooleanEffect {
+					input: input.name.clone(),
+					output: output.name.clone(),
+					comparison,
+				});
+			}
+		}
+	}
+
+	effects.sort_by(|a, b| a.comparison.rank(&b.comparison));
+	effects
+}
+
+/// Treats every tag, and every tag namespace, as a boolean input that holds on
+/// the day it was recorded. Each is compared against every numeric output
+/// on that day and on each of the following `coincidence_window` days, and
+/// the most significant lag is kept if it clears both the minimum support
+/// and the minimum confidence.
+fn find_tag_effects(fields: &[Field], entries: &[Entry], config: &Config) -> Vec<TagEffect> {
+	let outputs: Vec<&Field> = fields.iter().filter(|f| f.data_type.is_numeric() && f.category != FieldCategory::Input).collect();
+	let mut tag_days: HashMap<String, Vec<i32>> = HashMap::new();
+	for entry in entries {
+		let day = entry.date.to_julian_day();
+		let mut names: Vec<String> = entry.tags.clone();
+		names.extend(entry.tags.iter().flat_map(|t| tag_namespaces(t)).map(|ns| format!("{}:", ns)));
+		names.sort();
+		names.dedup();
+		for name in names {
+			tag_days.entry(name).or_default().push(day);
+		}
+	}
+
+	let mut effects = Vec::new();
+	for (tag, days) in &tag_days {
+		if days.len() < config.min_support as usize {
+			continue
+		}
+
+		for output in &outputs {
+			let mut best: Option<TagEffect> = None;
+			let mut tests = 0;
+			for lag in 0..=config.coincidence_window {
+				let mut with = Vec::new();
+				let mut without = Vec::new();
+				for entry in entries {
+					if let Some(value) = entry.numeric_fields.get(&output.name) {
+						if days.contains(&(entry.date.to_julian_day() - lag as i32)) {
+							with.push(*value);
+						} else {
+							without.push(*value);
+						}
+					}
+				}
+
+				if let Some(comparison) = compare_groups(&with, &without) {
+					tests += 1;
+					let better = match &best {
+						Some(b) => comparison.p_value < b.comparison.p_value,
+						None => true,
+					};
+					if better {
+						best = Some(TagEffect {
+							tag: tag.clone(),
+							output: output.name.clone(),
+							lag,
+							support: days.len(),
+							comparison,
+						});
+					}
+				}
+			}
+
+			if let Some(mut effect) = best {
+				effect.comparison.p_value = correct_for_tests(effect.comparison.p_value, tests);
+				if 1.0 - effect.comparison.p_value >= config.min_confidence {
+					effects.push(effect);
+				}
+			}
+		}
+	}
+
+	effects.sort_by(|a, b| a.comparison.rank(&b.comparison).then(a.tag.cmp(&b.tag)));
+	effects
+}
+
+/// Fewest points on either side of a state change before it is analysed.
+const MIN_SEGMENT_POINTS: usize = 5;
+
+/// Fits lines to each output over the `trend_window` days before and after
+/// every state start and end, and compares them where they meet.
+fn find_state_effects(dbh: &DbHandler, fields: &[Field], config: &Config) -> Result<Vec<StateEffect>> {
+	let outputs: Vec<&Field> = fields.iter().filter(|f| f.data_type.is_numeric() && f.category != FieldCategory::Input).collect();
+	let mut changes = Vec::new();
+	for state in dbh.get_states()? {
+		changes.push((state.name.clone(), true, state.start));
+		if let Some(end) = state.end {
+			changes.push((state.name, false, end));
+		}
+	}
+
+	let mut effects = Vec::new();
+	for (state, started, date) in changes {
+		let date = date.to_julian_day() as u32;
+		for output in &outputs {
+			let before = dbh.get_numeric_values_between_dates(&output.name, date.saturating_sub(config.trend_window), date - 1)?;
+			let after = dbh.get_numeric_values_between_dates(&output.name, date, date + config.trend_window - 1)?;
+			if let Some(effect) = compare_segments(&before, &after, date) {
+				effects.push(StateEffect {
+					state: state.clone(),
+					output: output.name.clone(),
+					started,
+					..effect
+				});
+			}
+		}
+	}
+
+	effects.sort_by(|a, b| a.level_shift_p.min(a.slope_change_p)
+		.partial_cmp(&b.level_shift_p.min(b.slope_change_p)).unwrap());
+	Ok(effects)
+}
+
+/// Fits a line to each segment and compares them at `date`, the first day
+/// of the second segment.
+fn compare_segments(before: &Vec<(u32, f64)>, after: &Vec<(u32, f64)>, date: u32) -> Option<StateEffect> {
+	if before.len() < MIN_SEGMENT_POINTS || after.len() < MIN_SEGMENT_POINTS {
+		return None
+	}
+
+	let (before, before_fit) = fit_segment(before);
+	let (after, after_fit) = fit_segment(after);
+	let (before_level, before_level_se) = before_fit.predict(date);
+	let (after_level, after_level_se) = after_fit.predict(date);
+	let df = (before.points + after.points) as f64 - 4.0;
+
+	let level_shift = after_level - before_level;
+	let level_shift_se = (before_level_se.powi(2) + after_level_se.powi(2)).sqrt();
+	let slope_change = after.slope - before.slope;
+	let slope_change_se = (before_fit.slope_se.powi(2) + after_fit.slope_se.powi(2)).sqrt();
+
+	Some(StateEffect {
+		state: String::new(),
+		output: String::new(),
+		started: true,
+		date,
+		before,
+		after,
+		level_shift,
+		level_shift_se,
+		level_shift_p: segment_p_value(level_shift, level_shift_se, df),
+		slope_change,
+		slope_change_se,
+		slope_change_p: segment_p_value(slope_change, slope_change_se, df),
+	})
+}
+
+/// Two-sided p-value for a difference between two segment fits.
+fn segment_p_value(difference: f64, standard_error: f64, df: f64) -> f64 {
+	if standard_error == 0.0 {
+		// Both lines fit exactly, so any difference at all is decisive.
+		return if difference == 0.0 { 1.0 } else { 0.0 }
+	}
+	student_t_p_value(difference / standard_error, df)
+}
+
+/// The uncertainty in a fitted line, needed to compare it with another.
+struct SegmentFit {
+	start: u32,
+	intercept: f64,
+	slope: f64,
+	slope_se: f64,
+	residual_se: f64,
+	points: f64,
+	mean_delta: f64,
+	sum_squares: f64,
+}
+
+impl SegmentFit {
+	/// The line's value on `date`, with its standard error.
+	fn predict(&self, date: u32) -> (f64, f64) {
+		let delta = date as f64 - self.start as f64;
+		let value = self.intercept + self.slope * delta;
+		let se = self.residual_se * (1.0 / self.points + (delta - self.mean_delta).powi(2) / self.sum_squares).sqrt();
+		(value, se)
+	}
+}
+
+fn fit_segment(values: &Vec<(u32, f64)>) -> (Trend, SegmentFit) {
+	let (slope, intercept) = calculate_single_lobf(values);
+	let stddev = calculate_std_dev(values, slope, intercept);
+	let start = values.first().unwrap().0;
+	let n = values.len() as f64;
+	let mean_delta = values.iter().map(|(d, _)| (d - start) as f64).sum::<f64>() / n;
+	let sum_squares: f64 = values.iter().map(|(d, _)| ((d - start) as f64 - mean_delta).powi(2)).sum();
+	// calculate_std_dev divides by n; the standard errors want n - 2.
+	let residual_se = stddev * (n / (n - 2.0)).sqrt();
+
+	let trend = Trend {
+		start,
+		end: values.last().unwrap().0,
+		orig_val: intercept,
+		slope,
+		stddev,
+		points: values.len() as u32,
+	};
+	let fit = SegmentFit {
+		start,
+		intercept,
+		slope,
+		slope_se: residual_se / sum_squares.sqrt(),
+		residual_se,
+		points: n,
+		mean_delta,
+		sum_squares,
+	};
+	(trend, fit)
+}
+
+impl Comparison {
+	/// Orders comparisons from most to least significant, breaking ties by
+	/// the size of the effect.
+	fn rank(&self, other: &Comparison) -> std::cmp::Ordering {
+		self.p_value.partial_cmp(&other.p_value).unwrap()
+			.then(other.effect_size.abs().partial_cmp(&self.effect_size.abs()).unwrap())
+	}
+}
+
+/// Runs Welch's t-test on two samples, if both are large enough to compare.
+pub fn compare_groups(with: &[f64], without: &[f64]) -> Option<Comparison> {
+	if with.len() < MIN_GROUP_SIZE || without.len() < MIN_GROUP_SIZE {
+		return None
+	}
+
+	let (n1, n2) = (with.len() as f64, without.len() as f64);
+	let (mean1, mean2) = (mean(with), mean(without));
+	let (var1, var2) = (sample_variance(with), sample_variance(without));
+	let diff = mean1 - mean2;
+
+	let pooled_sd = (((n1 - 1.0) * var1 + (n2 - 1.0) * var2) / (n1 + n2 - 2.0)).sqrt();
+	let standard_error = (var1 / n1 + var2 / n2).sqrt();
+	let (effect_size, p_value) = if standard_error == 0.0 {
+		// Both groups are constant, so any difference at all is decisive.
+		if diff == 0.0 { (0.0, 1.0) } else { (diff.signum() * f64::INFINITY, 0.0) }
+	} else {
+		let t = diff / standard_error;
+		let df = (var1 / n1 + var2 / n2).powi(2)
+			/ ((var1 / n1).powi(2) / (n1 - 1.0) + (var2 / n2).powi(2) / (n2 - 1.0));
+		(diff / pooled_sd, student_t_p_value(t, df))
+	};
+
+	Some(Comparison {
+		with_count: with.len(),
+		without_count: without.len(),
+		with_mean: mean1,
+		without_mean: mean2,
+		effect_size,
+		p_value,
+	})
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+	values.iter().sum::<f64>() / values.len() as f64
+}
+
+pub fn sample_variance(values: &[f64]) -> f64 {
+	let m = mean(values);
+	values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() as f64 - 1.0)
+}
+
+/// Two-sided p-value of a Student's t statistic with `df` degrees of freedom.
+pub fn student_t_p_value(t: f64, df: f64) -> f64 {
+	if !t.is_finite() {
+		return 0.0
+	}
+	incomplete_beta(df / 2.0, 0.5, df / (df + t * t)).clamp(0.0, 1.0)
+}
+
+/// The regularized incomplete beta function I_x(a, b), evaluated with the
+/// continued fraction from Numerical Recipes.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+	if x <= 0.0 {
+		return 0.0
+	}
+	if x >= 1.0 {
+		return 1.0
+	}
+
+	let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+	if x < (a + 1.0) / (a + b + 2.0) {
+		front * beta_continued_fraction(a, b, x) / a
+	} else {
+		1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+	}
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+	const TINY: f64 = 1e-30;
+	// Applies one term of the fraction using the modified Lentz method,
+	// returning the factor by which it changes the result.
+	fn step(numerator: f64, c: &mut f64, d: &mut f64) -> f64 {
+		*d = 1.0 + numerator * *d;
+		if d.abs() < TINY {
+			*d = TINY;
+		}
+		*c = 1.0 + numerator / *c;
+		if c.abs() < TINY {
+			*c = TINY;
+		}
+		*d = 1.0 / *d;
+		*d * *c
+	}
+
+	let mut c = 1.0;
+	let mut d = 1.0 - (a + b) * x / (a + 1.0);
+	if d.abs() < TINY {
+		d = TINY;
+	}
+	d = 1.0 / d;
+	let mut h = d;
+	for m in 1..200 {
+		let m = m as f64;
+		h *= step(m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)), &mut c, &mut d);
+		let delta = step(-(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)), &mut c, &mut d);
+		h *= delta;
+		if (delta - 1.0).abs() < 1e-12 {
+			break
+		}
+	}
+
+	h
+}
+
+/// Lanczos approximation of ln(Γ(x)).
+fn ln_gamma(x: f64) -> f64 {
+	const COEFFICIENTS: [f64; 6] = [
+		76.18009172947146,
+		-86.50532032941677,
+		24.01409824083091,
+		-1.231739572450155,
+		0.1208650973866179e-2,
+		-0.5395239384953e-5,
+	];
+	let mut y = x;
+	let tmp = x + 5.5;
+	let tmp = tmp - (x + 0.5) * tmp.ln();
+	let mut series = 1.000000000190015;
+	for coefficient in COEFFICIENTS {
+		y += 1.0;
+		series += coefficient / y;
+	}
+
+	-tmp + (2.5066282746310005 * series / x).ln()
+}
+
 #[cfg(test)]
 mod analysis_test {
 	use super::*;
 	use crate::test_utils::*;
+	use std::path::PathBuf;
 
 	#[test]
 	fn test_slope_derivation() {
@@ -268,7 +1077,7 @@ mod analysis_test {
 		assert!(trend.stddev < 6.0);
 
 		let trend = find_trends(&dbh, &String::from("switch"), 22120, 22134).unwrap();
-		let trend = extend_trend(&trend, &String::from("switch"), &dbh);
+		let trend = extend_trend(&trend, &String::from("switch"), &dbh, &Config::default()).unwrap();
 		// println!("{:#?}", trend);
 		assert!(trend.slope > 0.4);
 		assert!(trend.slope < 0.6);
@@ -296,7 +1105,7 @@ mod analysis_test {
 
 		for (col, slope) in cols {
 			// instant = time::Instant::now();
-			let trends = find_all_trends(&dbh, &String::from(col));
+			let trends = find_all_trends(&dbh, &String::from(col), &Config::default());
 			// println!("Finding trends took {}", time::Instant::now() - instant);
 			assert!(trends.is_ok());
 			let trends = trends.unwrap();
@@ -308,7 +1117,7 @@ mod analysis_test {
 			assert!(trend.slope > slope - 0.1);
 			assert!(trend.slope < slope + 0.1);
 		}
-		let trends = find_all_trends(&dbh, &String::from("switch"));
+		let trends = find_all_trends(&dbh, &String::from("switch"), &Config::default());
 		assert!(trends.is_ok());
 		let trends = trends.unwrap();
 		assert!(trends.len() == 2);
@@ -339,11 +1148,380 @@ mod analysis_test {
 		// println!("For medi: {:#?}", outliers.len());
 		// let outliers = detect_outliers(&dbh, &String::from("hard"));
 		// println!("For hard: {:#?}", outliers.len());
-		let outliers = detect_outliers(&dbh, &String::from("outliers"));
+		let outliers = detect_outliers(&dbh, &String::from("outliers"), &Config::default()).unwrap();
 		assert!(outliers.contains(&22129));
 		assert!(outliers.contains(&22138));
 		// println!("{:#?}", outliers.len());
 		// assert!(outliers.len() == 2);
 
+		// Spikes older than the timeline are not looked at.
+		let config = Config { max_timeline: 100, ..Config::default() };
+		let outliers = detect_outliers(&dbh, &String::from("outliers"), &config).unwrap();
+		assert!(!outliers.contains(&22129));
+		assert!(!outliers.contains(&22138));
+
+	}
+
+	#[test]
+	fn test_report() {
+		let dbh = setup_db("test_report.db");
+		import_csv(&dbh, "test_trends.csv");
+		let _ = dbh.insert_field(&Field {
+			name: String::from("sparse"),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		});
+		let config = Config {
+			db_path: String::from("test_report.db"),
+			..Config::default()
+		};
+
+		let report = analyze(&config).unwrap();
+		assert!(report.fields.len() == 6);
+		let sparse = report.fields.iter().find(|f| f.field == "sparse").unwrap();
+		assert!(sparse.points == 0);
+		assert!(sparse.trends.is_empty());
+
+		let switch = report.fields.iter().find(|f| f.field == "switch").unwrap();
+		assert!(switch.points == 179);
+		assert!(switch.trends.len() == 2);
+		assert!(switch.trends.iter().all(|t| t.points > 0 && t.points as usize <= switch.points));
+		let outliers = report.fields.iter().find(|f| f.field == "outliers").unwrap();
+		assert!(outliers.outliers.contains(&22129));
+
+		// Each field with a trend yields one trend finding, and rerunning the
+		// analysis gives the same identities.
+		let findings = report.recommendations();
+		let trends: Vec<&Recommendation> = findings.iter().filter(|r| r.kind == RecommendationType::Trend).collect();
+		assert!(trends.len() == report.fields.iter().filter(|f| !f.trends.is_empty()).count());
+		assert!(findings.iter().all(|r| (0.0..=1.0).contains(&r.confidence)));
+		let rerun = analyze(&config).unwrap().recommendations();
+		assert!(rerun.iter().map(|r| (r.kind, &r.output, &r.input)).eq(findings.iter().map(|r| (r.kind, &r.output, &r.input))));
+	}
+
+	#[test]
+	fn test_recommendation_staleness() {
+		let dbh = setup_db("test_recommendation_staleness.db");
+		let fields = [
+			("mood", FieldCategory::Output, FieldType::Numeric),
+			("sleep", FieldCategory::Input, FieldType::Numeric),
+			("exercise", FieldCategory::Input, FieldType::Boolean),
+		];
+		for (name, category, data_type) in fields {
+			dbh.insert_field(&Field { name: String::from(name), category, data_type, active: true, meta: FieldMeta::default() }).unwrap();
+		}
+		let start = Date::from_calendar_date(2022, time::Month::May, 1).unwrap();
+		for day in 0..40 {
+			let sleep = 6.0 + (day % 5) as f64;
+			let exercise = day % 3 == 0;
+			dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([
+					(String::from("sleep"), sleep),
+					(String::from("mood"), sleep / 2.0 + if exercise { 2.0 } else { 0.0 } + (day % 2) as f64 * 0.5),
+				]),
+				boolean_fields: HashMap::from([(String::from("exercise"), exercise)]),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			}).unwrap();
+		}
+		let config = Config {
+			db_path: String::from("test_recommendation_staleness.db"),
+			..Config::default()
+		};
+
+		let report = analyze(&config).unwrap();
+		dbh.upsert_recommendations(&report.recommendations(), 0.0).unwrap();
+		let stored = dbh.get_recommendations(true).unwrap();
+		let sleep = stored.iter().find(|r| r.kind == RecommendationType::Correlation && r.input == "sleep").unwrap();
+		assert!(sleep.lag == Some(0));
+		assert!(stored.iter().any(|r| r.kind == RecommendationType::Boolean && r.input == "exercise"));
+		assert!(stored.iter().all(|r| !r.stale));
+
+		// Rerunning finds the same findings again; once exercise is a number
+		// it is no longer compared as a boolean, so that finding goes stale.
+		dbh.upsert_recommendations(&analyze(&config).unwrap().recommendations(), 0.0).unwrap();
+		assert!(dbh.get_recommendations(true).unwrap().iter().map(|r| r.id).eq(stored.iter().map(|r| r.id)));
+		dbh.convert_field("exercise", FieldType::Numeric, false).unwrap();
+		dbh.upsert_recommendations(&analyze(&config).unwrap().recommendations(), 0.0).unwrap();
+		let rerun = dbh.get_recommendations(true).unwrap();
+		let boolean = rerun.iter().find(|r| r.kind == RecommendationType::Boolean).unwrap();
+		assert!(boolean.stale && rerun.last() == Some(boolean));
+		assert!(rerun.iter().any(|r| r.id == sleep.id && !r.stale));
+	}
+
+	#[test]
+	fn test_stale_field() {
+		let dbh = setup_db("test_stale_field.db");
+		for name in ["mood", "stale"] {
+			let _ = dbh.insert_field(&Field {
+				name: String::from(name),
+				category: FieldCategory::Output,
+				data_type: FieldType::Numeric,
+				active: name == "mood",
+				meta: FieldMeta::default(),
+			});
+		}
+		// The deactivated field was only recorded long before the window
+		// that trends are looked for in.
+		let start = Date::from_calendar_date(2020, time::Month::January, 1).unwrap();
+		for day in 0..40 {
+			let name = if day < 20 { "stale" } else { "mood" };
+			let date = start + time::Duration::days(if day < 20 { day } else { 1000 + day });
+			let _ = dbh.insert_entry(&Entry {
+				date,
+				numeric_fields: HashMap::from([(String::from(name), day as f64 % 7.0)]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			});
+		}
+		let config = Config {
+			db_path: String::from("test_stale_field.db"),
+			..Config::default()
+		};
+
+		let report = analyze(&config).unwrap();
+		let stale = report.fields.iter().find(|f| f.field == "stale").unwrap();
+		assert!(stale.points == 0 && stale.trends.is_empty());
+		assert!(report.fields.iter().find(|f| f.field == "mood").unwrap().points == 20);
+	}
+
+	#[test]
+	fn test_student_t() {
+		// Critical values from standard t tables.
+		assert!((student_t_p_value(2.228, 10.0) - 0.05).abs() < 0.001);
+		assert!((student_t_p_value(2.042, 30.0) - 0.05).abs() < 0.001);
+		assert!((student_t_p_value(-3.169, 10.0) - 0.01).abs() < 0.001);
+		assert!((student_t_p_value(0.0, 5.0) - 1.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn test_prepared_entries() {
+		let fields = vec![
+			Field {
+				name: String::from("workout"),
+				category: FieldCategory::Input,
+				data_type: FieldType::Categorical,
+				active: true,
+				meta: FieldMeta { choices: vec![String::from("run"), String::from("yoga")], ..FieldMeta::default() },
+			},
+			Field { name: String::from("bedtime"), category: FieldCategory::Input, data_type: FieldType::TimeOfDay, active: true, meta: FieldMeta::default() },
+			Field { name: String::from("mood"), category: FieldCategory::Output, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() },
+		];
+		let start = Date::from_julian_day(22120).unwrap();
+		// Mood is higher after a run; workouts are skipped every fifth day.
+		let entries: Vec<Entry> = (0..30).map(|day| {
+			let workout = ["run", "yoga"][day as usize % 2];
+			let mood = if workout == "run" { 7.0 } else { 5.0 } + (day % 3) as f64 * 0.5;
+			Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([
+					(String::from("mood"), mood),
+					(String::from("bedtime"), if day % 2 == 0 { 1430.0 } else { 15.0 }),
+				]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: if day % 5 == 4 {
+					HashMap::new()
+				} else {
+					HashMap::from([(String::from("workout"), String::from(workout))])
+				},
+				tags: Vec::new(),
+				note: None,
+			}
+		}).collect();
+
+		let (fields, entries) = prepare_entries(&fields, entries);
+		assert!(fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>() == vec!["workout=run", "workout=yoga", "bedtime", "mood"]);
+		assert!(entries[0].boolean_fields.get("workout=run") == Some(&true));
+		assert!(entries[0].boolean_fields.get("workout=yoga") == Some(&false));
+		assert!(entries[4].boolean_fields.is_empty());
+		// Bedtimes straddle midnight, so they unwrap to either side of it.
+		assert!(entries[0].numeric_fields["bedtime"].round() == -10.0);
+		assert!(entries[1].numeric_fields["bedtime"].round() == 15.0);
+
+		let effects = find_boolean_effects(&fields, &entries);
+		let run = effects.iter().find(|e| e.input == "workout=run").unwrap();
+		assert!(run.comparison.with_mean > run.comparison.without_mean);
+		assert!(run.comparison.with_count + run.comparison.without_count == 24);
+		assert!(run.comparison.p_value < 0.001);
+	}
+
+	#[test]
+	fn test_boolean_effects() {
+		let fields = vec![
+			Field { name: String::from("exercise"), category: FieldCategory::Input, data_type: FieldType::Boolean, active: true, meta: FieldMeta::default() },
+			Field { name: String::from("rain"), category: FieldCategory::Input, data_type: FieldType::Boolean, active: true, meta: FieldMeta::default() },
+			Field { name: String::from("mood"), category: FieldCategory::Output, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() },
+		];
+		let start = Date::from_julian_day(22120).unwrap();
+		let entries: Vec<Entry> = (0..40).map(|day| {
+			let exercise = day % 2 == 0;
+			let mood = if exercise { 7.0 } else { 4.0 } + (day % 5) as f64 * 0.5;
+			Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([(String::from("mood"), mood)]),
+				boolean_fields: HashMap::from([
+					(String::from("exercise"), exercise),
+					(String::from("rain"), day % 4 < 2),
+				]),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			}
+		}).collect();
+
+		let effects = find_boolean_effects(&fields, &entries);
+		assert!(effects.len() == 2);
+		let exercise = effects.first().unwrap();
+		assert!(exercise.input == "exercise" && exercise.output == "mood");
+		assert!(exercise.comparison.with_count == 20 && exercise.comparison.without_count == 20);
+		assert!(exercise.comparison.effect_size > 2.0);
+		assert!(exercise.comparison.p_value < 0.001);
+		let rain = effects.last().unwrap();
+		assert!(rain.input == "rain");
+		assert!(rain.comparison.p_value > 0.2);
+	}
+
+	#[test]
+	fn test_tag_effects() {
+		let fields = vec![
+			Field { name: String::from("mood"), category: FieldCategory::Output, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() },
+		];
+		let start = Date::from_julian_day(22120).unwrap();
+		let entries: Vec<Entry> = (0..60).map(|day| {
+			// Gluten drags mood down the following day; chatting to anyone
+			// has no effect at all.
+			let mut mood = 6.0 + (day % 3) as f64 * 0.5;
+			if day % 6 == 1 {
+				mood -= 3.0;
+			}
+			let mut tags = Vec::new();
+			if day % 6 == 0 {
+				tags.push(String::from("ate:gluten"));
+			}
+			if day % 5 == 0 {
+				tags.push(String::from("spoke:diego"));
+			} else if day % 5 == 2 {
+				tags.push(String::from("spoke:anna"));
+			}
+			Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([(String::from("mood"), mood)]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags,
+				note: None,
+			}
+		}).collect();
+
+		let effects = find_tag_effects(&fields, &entries, &Config::default());
+		let gluten = effects.iter().find(|e| e.tag == "ate:gluten").unwrap();
+		assert!(gluten.lag == 1);
+		assert!(gluten.support == 10);
+		assert!(gluten.comparison.effect_size < -1.0);
+		assert!(effects.iter().any(|e| e.tag == "ate:"));
+		assert!(!effects.iter().any(|e| e.tag.starts_with("spoke:")));
+
+		let strict = Config { min_support: 11, ..Config::default() };
+		assert!(find_tag_effects(&fields, &entries, &strict).is_empty());
+	}
+
+	#[test]
+	fn test_lag_correction() {
+		let correlation = |lag, p| Correlation {
+			input: String::from("sleep"),
+			output: String::from("mood"),
+			lag,
+			samples: 30,
+			pearson: 0.4,
+			pearson_p: p,
+			spearman: 0.4,
+			spearman_p: p,
+		};
+		let best = strongest_correlations(vec![correlation(0, 0.3), correlation(1, 0.02), correlation(2, 0.5), correlation(3, 0.4)]);
+		assert!(best.len() == 1);
+		assert!(best[0].lag == 1);
+		assert!((best[0].pearson_p - 0.08).abs() < 1e-9);
+		assert!((best[0].spearman_p - 0.08).abs() < 1e-9);
+		assert!(correct_for_tests(0.4, 4) == 1.0);
+	}
+
+	#[test]
+	fn test_state_effects() {
+		let dbh = setup_db("test_state_effects.db");
+		let _ = dbh.insert_field(&Field {
+			name: String::from("mood"),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		});
+		let start = Date::from_calendar_date(2022, time::Month::May, 1).unwrap();
+		// Medication starts on day 40 and lifts mood by two points; moving
+		// house on day 20 does nothing.
+		for day in 0..80 {
+			let noise = [0.3, -0.2, 0.1, -0.4, 0.2][day as usize % 5];
+			let mood = 5.0 + noise + if day >= 40 { 2.0 } else { 0.0 };
+			let _ = dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([(String::from("mood"), mood)]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			});
+		}
+		dbh.toggle_state("medication", Some(20.0), start + time::Duration::days(40)).unwrap();
+		dbh.toggle_state("moved", None, start + time::Duration::days(20)).unwrap();
+
+		let config = Config { trend_window: 15, ..Config::default() };
+		let effects = find_state_effects(&dbh, &dbh.get_fields().unwrap(), &config).unwrap();
+		assert!(effects.len() == 2);
+		let medication = effects.first().unwrap();
+		assert!(medication.state == "medication" && medication.started);
+		assert!((medication.level_shift - 2.0).abs() < 0.5);
+		assert!(medication.level_shift_p < 0.001);
+		assert!(medication.slope_change.abs() < 0.05);
+		let moved = effects.last().unwrap();
+		assert!(moved.state == "moved");
+		assert!(moved.level_shift.abs() < 0.5);
+		assert!(moved.level_shift_p > 0.05);
+	}
+
+	#[test]
+	fn test_constant_state_effects() {
+		let dbh = setup_db("test_constant_state_effects.db");
+		let _ = dbh.insert_field(&Field {
+			name: String::from("mood"),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		});
+		let start = Date::from_calendar_date(2022, time::Month::May, 1).unwrap();
+		for day in 0..80 {
+			let _ = dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([(String::from("mood"), 5.0)]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			});
+		}
+		dbh.toggle_state("medication", None, start + time::Duration::days(40)).unwrap();
+
+		// A flat output fits both sides exactly, which is no evidence of an
+		// effect.
+		let config = Config { trend_window: 15, ..Config::default() };
+		let effects = find_state_effects(&dbh, &dbh.get_fields().unwrap(), &config).unwrap();
+		assert!(effects.len() == 1);
+		assert!(effects[0].level_shift_p == 1.0);
+		assert!(effects[0].slope_change_p == 1.0);
 	}
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+use crate::prelude::*;
+use csv::Writer;
+
+/// How strongly a numeric input on one day tracks a numeric output `lag`
+/// days later.
+pub struct Correlation {
+	pub input: String,
+	pub output: String,
+	pub lag: u32,
+	/// Days on which both the input and the lagged output were recorded.
+	pub samples: usize,
+	pub pearson: f64,
+	pub pearson_p: f64,
+	pub spearman: f64,
+	pub spearman_p: f64,
+}
+
+/// Correlates every numeric input or hybrid field with every numeric output
+/// or hybrid field, shifting the output by each lag from 0 to `max_lag`
+/// days. Days on which either value is missing are skipped, as are pairs
+/// with too few samples or no variation to correlate.
+pub fn correlation_matrix(fields: &[Field], entries: &[Entry], max_lag: u32) -> Vec<Correlation> {
+	let inputs = fields.iter().filter(|f| f.data_type.is_numeric() && f.category != FieldCategory::Output);
+	let outputs: Vec<&Field> = fields.iter().filter(|f| f.data_type.is_numeric() && f.category != FieldCategory::Input).collect();
+	let by_day: HashMap<i32, &Entry> = entries.iter().map(|e| (e.date.to_julian_day(), e)).collect();
+	let mut correlations = Vec::new();
+
+	for input in inputs {
+		for output in outputs.iter().filter(|o| o.name != input.name) {
+			for lag in 0..=max_lag {
+				let mut xs = Vec::new();
+				let mut ys = Vec::new();
+				for entry in entries {
+					let x = entry.numeric_fields.get(&input.name);
+					let later = by_day.get(&(entry.date.to_julian_day() + lag as i32));
+					let y = later.and_then(|e| e.numeric_fields.get(&output.name));
+					if let (Some(x), Some(y)) = (x, y) {
+						xs.push(*x);
+						ys.push(*y);
+					}
+				}
+
+				let pearson = pearson(&xs, &ys);
+				let spearman = pearson_of_ranks(&xs, &ys);
+				if let (Some(pearson), Some(spearman)) = (pearson, spearman) {
+					correlations.push(Correlation {
+						input: input.name.clone(),
+						output: output.name.clone(),
+						lag,
+						samples: xs.len(),
+						pearson,
+						pearson_p: correlation_p_value(pearson, xs.len()),
+						spearman,
+						spearman_p: correlation_p_value(spearman, xs.len()),
+					});
+				}
+			}
+		}
+	}
+
+	correlations
+}
+
+pub fn print_matrix(correlations: &[Correlation]) {
+	if correlations.is_empty() {
+		println!("Not enough paired numeric data to correlate any inputs with outputs.");
+		return
+	}
+
+	let input_width = correlations.iter().map(|c| c.input.len()).max().unwrap().max(5);
+	let output_width = correlations.iter().map(|c| c.output.len()).max().unwrap().max(6);
+	println!(
+		"{:iw$}  {:ow$}  {:>3}  {:>5}  {:>16}  {:>16}",
+		"input", "output", "lag", "n", "pearson (p)", "spearman (p)",
+		iw = input_width, ow = output_width,
+	);
+	for c in correlations {
+		println!(
+			"{:iw$}  {:ow$}  {:>3}  {:>5}  {:>+6.3} ({:>6.4})  {:>+6.3} ({:>6.4})",
+			c.input, c.output, c.lag, c.samples, c.pearson, c.pearson_p, c.spearman, c.spearman_p,
+			iw = input_width, ow = output_width,
+		);
+	}
+}
+
+pub fn export_matrix(correlations: &[Correlation], path: &Path) -> Result<()> {
+	let mut writer = Writer::from_path(path)?;
+	writer.write_record(["input", "output", "lag", "samples", "pearson", "pearson_p", "spearman", "spearman_p"])?;
+	for c in correlations {
+		writer.write_record([
+			c.input.clone(),
+			c.output.clone(),
+			c.lag.to_string(),
+			c.samples.to_string(),
+			c.pearson.to_string(),
+			c.pearson_p.to_string(),
+			c.spearman.to_string(),
+			c.spearman_p.to_string(),
+		])?;
+	}
+	writer.flush()?;
+
+	Ok(())
+}
+
+/// Pearson's r, or `None` if there are too few samples or either series is
+/// constant.
+fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+	if xs.len() < 3 {
+		return None
+	}
+
+	let (mean_x, mean_y) = (mean(xs), mean(ys));
+	let mut covariance = 0.0;
+	let mut variance_x = 0.0;
+	let mut variance_y = 0.0;
+	for (x, y) in xs.iter().zip(ys) {
+		covariance += (x - mean_x) * (y - mean_y);
+		variance_x += (x - mean_x).powi(2);
+		variance_y += (y - mean_y).powi(2);
+	}
+	if variance_x == 0.0 || variance_y == 0.0 {
+		return None
+	}
+
+	Some((covariance / (variance_x * variance_y).sqrt()).clamp(-1.0, 1.0))
+}
+
+/// Spearman's rho, computed as Pearson's r of the ranks.
+fn pearson_of_ranks(xs: &[f64], ys: &[f64]) -> Option<f64> {
+	pearson(&ranks(xs), &ranks(ys))
+}
+
+/// Ranks values from 1, giving tied values the average of their ranks.
+fn ranks(values: &[f64]) -> Vec<f64> {
+	let mut order: Vec<usize> = (0..values.len()).collect();
+	order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap());
+
+	let mut ranks = vec![0.0; values.len()];
+	let mut i = 0;
+	while i < order.len() {
+		let mut j = i;
+		while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+			j += 1;
+		}
+		let rank = (i + j) as f64 / 2.0 + 1.0;
+		for index in &order[i..=j] {
+			ranks[*index] = rank;
+		}
+		i = j + 1;
+	}
+
+	ranks
+}
+
+/// Two-sided p-value for a correlation coefficient over `n` samples, using
+/// the t distribution with n - 2 degrees of freedom.
+fn correlation_p_value(r: f64, n: usize) -> f64 {
+	let df = n as f64 - 2.0;
+	if df <= 0.0 {
+		return 1.0
+	}
+	if r.abs() >= 1.0 {
+		return 0.0
+	}
+
+	student_t_p_value(r * (df / (1.0 - r * r)).sqrt(), df)
+}
+
+#[cfg(test)]
+mod correlation_test {
+	use super::*;
+
+	#[test]
+	fn test_ranks() {
+		assert!(ranks(&[10.0, 30.0, 20.0]) == vec![1.0, 3.0, 2.0]);
+		assert!(ranks(&[5.0, 1.0, 5.0, 2.0]) == vec![3.5, 1.0, 3.5, 2.0]);
+	}
+
+	#[test]
+	fn test_lagged_correlation() {
+		let fields = vec![
+			Field { name: String::from("sleep_quality"), category: FieldCategory::Input, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() },
+			Field { name: String::from("mood"), category: FieldCategory::Output, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() },
+		];
+		let start = Date::from_julian_day(22120).unwrap();
+		// Sleep follows an irregular pattern and sets the next day's mood;
+		// every seventh day is missing entirely.
+		let sleep = |day: i64| ((day * 7) % 11) as f64;
+		let entries: Vec<Entry> = (0..80).filter(|day| day % 7 != 3).map(|day| {
+			let mood = if day == 0 { 5.0 } else { sleep(day - 1) * 0.5 + 2.0 };
+			Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([
+					(String::from("sleep_quality"), sleep(day)),
+					(String::from("mood"), mood),
+				]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			}
+		}).collect();
+
+		let correlations = correlation_matrix(&fields, &entries, 2);
+		assert!(correlations.len() == 3);
+		let next_day = correlations.iter().find(|c| c.lag == 1).unwrap();
+		assert!(next_day.input == "sleep_quality" && next_day.output == "mood");
+		assert!(next_day.pearson > 0.99);
+		assert!(next_day.spearman > 0.99);
+		assert!(next_day.pearson_p < 0.001);
+		// Neither the missing days nor the days after them can be paired.
+		assert!(next_day.samples < entries.len() - 1);
+		let same_day = correlations.iter().find(|c| c.lag == 0).unwrap();
+		assert!(same_day.pearson.abs() < 0.5);
+		assert!(same_day.samples == entries.len());
+	}
+}
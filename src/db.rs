@@ -1,41 +1,140 @@
 extern crate rusqlite;
 extern crate anyhow;
 
-use rusqlite::{Connection, Error};
-use std::path::PathBuf;
+use rusqlite::{named_params, Connection, Error, OptionalExtension, Row, ToSql};
+use rusqlite::types::Type;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use time::Date;
 
 use crate::util::*;
 
+type Migration = fn(&DbHandler) -> Result<(), Error>;
+
+/// Upgrades applied to older databases, in order. A database at schema
+/// version `n` (SQLite's `user_version`) has had the first `n` applied, so
+/// new migrations must only ever be appended.
+const MIGRATIONS: [Migration; 8] = [
+	DbHandler::migrate_unique_dates,
+	DbHandler::migrate_state_dates,
+	DbHandler::migrate_tag_table,
+	DbHandler::migrate_field_meta,
+	DbHandler::migrate_field_choices,
+	DbHandler::migrate_notes,
+	DbHandler::migrate_column_types,
+	DbHandler::migrate_recommendation_lags,
+];
+
+/// The schema version created by `initialize_db`.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
 pub struct DbHandler {
 	conn: Connection,
 	commands: HashMap<&'static str, &'static str>
 }
 
 impl DbHandler {
+	/// Opens a database, upgrading it to the current schema first.
 	pub fn new(path: PathBuf) -> Self {
-		let conn = Connection::open(path).unwrap();
+		let dbh = Self::connect(&path).unwrap();
+		dbh.migrate(&path).expect("Failed upgrading the database");
+		dbh
+	}
+
+	/// Brings the database at `path` up to the current schema, returning the
+	/// location of the backup taken beforehand if anything had to change.
+	pub fn upgrade(path: &Path) -> Result<Option<PathBuf>> {
+		Self::connect(path)?.migrate(path)
+	}
+
+	fn connect(path: &Path) -> Result<Self, Error> {
+		let conn = Connection::open(path)?;
 		let mut commands = HashMap::new();
 		commands.insert("test", "test result");
-		commands.insert("insert field column", "ALTER TABLE entries ADD COLUMN :name :type;");
-		commands.insert("insert field entry", "INSERT INTO fields (name, category, type, active) VALUES (':name', ':category', ':type', true);");
-		commands.insert("insert entry", "INSERT INTO entries (date, ENTRY_COLUMNS) VALUES (ENTRY_VALUES);");
-		commands.insert("get fields", "SELECT name, category, type, active FROM fields;");
-		commands.insert("get active fields", "SELECT name, category, type, active FROM fields WHERE active = true ORDER BY type ASC;");
-		commands.insert("get entries", "SELECT date, ENTRY_COLUMNS, tags FROM entries;");
-		commands.insert("get numeric field entries", "SELECT :column FROM entries WHERE date >= :start AND date <= :end ORDER BY date ASC;");
-		commands.insert("get dates and numeric field entries", "SELECT date, :column FROM entries ORDER BY date ASC;");
-		commands.insert("get dates and numeric field entries between dates", "SELECT date, :column FROM entries WHERE date >= :start AND date <= :end ORDER BY date ASC;");
+		commands.insert("insert field column", "ALTER TABLE entries ADD COLUMN FIELD_NAME FIELD_TYPE;");
+		commands.insert("insert field entry", "INSERT INTO fields (name, category, type, active, min, max, unit, step, description, choices) VALUES (:name, :category, :type, true, :min, :max, :unit, :step, :description, :choices);");
+		commands.insert("set field meta", "UPDATE fields SET min = :min, max = :max, unit = :unit, step = :step, description = :description, choices = :choices WHERE name = :name;");
+		commands.insert("add field meta", "ALTER TABLE fields ADD COLUMN min real; ALTER TABLE fields ADD COLUMN max real; ALTER TABLE fields ADD COLUMN unit text; ALTER TABLE fields ADD COLUMN step real; ALTER TABLE fields ADD COLUMN description text;");
+		commands.insert("add field choices", "ALTER TABLE fields ADD COLUMN choices text;");
+		commands.insert("insert entry", "INSERT INTO entries (ENTRY_COLUMNS) VALUES (ENTRY_VALUES);");
+		commands.insert("get fields", "SELECT name, category, type, active, min, max, unit, step, description, choices FROM fields ORDER BY type ASC;");
+		commands.insert("rename field entry", "UPDATE fields SET name = :new WHERE name = :old;");
+		commands.insert("set field type", "UPDATE fields SET type = :type WHERE name = :name;");
+		commands.insert("rename field recommendations", "UPDATE recommendations SET output = :new WHERE output = :old;");
+		commands.insert("rename field recommendation inputs", "UPDATE recommendations SET input = :new WHERE type IN ('correlation', 'boolean') AND input = :old;");
+		commands.insert("get entry columns", "SELECT name, type FROM pragma_table_info('entries') WHERE name NOT IN ('id', 'date') ORDER BY cid ASC;");
+		commands.insert("create rebuilt entries", "CREATE TABLE entries_rebuild (id integer primary key, date integer not null unique ENTRY_COLUMNS);");
+		commands.insert("copy rebuilt entries", "INSERT INTO entries_rebuild (id, date ENTRY_COLUMNS) SELECT id, date ENTRY_VALUES FROM entries;");
+		commands.insert("drop entries", "DROP TABLE entries;");
+		commands.insert("rename rebuilt entries", "ALTER TABLE entries_rebuild RENAME TO entries;");
+		commands.insert("count non binary values", "SELECT COUNT(*) FROM entries WHERE COLUMN IS NOT NULL AND COLUMN NOT IN (0, 1);");
+		commands.insert("set field active", "UPDATE fields SET active = :active WHERE name = :name;");
+		commands.insert("get active fields", "SELECT name, category, type, active, min, max, unit, step, description, choices FROM fields WHERE active = true ORDER BY type ASC;");
+		commands.insert("get entries", "SELECT ENTRY_COLUMNS FROM entries;");
+		commands.insert("get entry", "SELECT ENTRY_COLUMNS FROM entries WHERE date = :date;");
+		commands.insert("delete entry", "DELETE FROM entries WHERE date = :date;");
+		commands.insert("update entry", "UPDATE entries SET ENTRY_ASSIGNMENTS WHERE date = ?;");
+		commands.insert("get numeric field entries", "SELECT COLUMN FROM entries WHERE COLUMN NOT NULL AND date >= :start AND date <= :end ORDER BY date ASC;");
+		commands.insert("get dates and numeric field entries", "SELECT date, COLUMN FROM entries WHERE COLUMN NOT NULL ORDER BY date ASC;");
+		commands.insert("get dates and numeric field entries between dates", "SELECT date, COLUMN FROM entries WHERE COLUMN NOT NULL AND date >= :start AND date <= :end ORDER BY date ASC;");
 		commands.insert("get latest date", "SELECT MAX(date) FROM entries;");
 		commands.insert("get earliest date", "SELECT MIN(date) FROM entries;");
-		commands.insert("count entries", "SELECT COUNT (*) FROM entries WHERE :column NOT NULL AND date >= :start AND date <= :end;");
+		commands.insert("insert state", "INSERT INTO states (name, amount, start_date) VALUES (:name, :amount, :start);");
+		commands.insert("close state", "UPDATE states SET end_date = :end WHERE name = :name AND end_date IS NULL;");
+		commands.insert("get states", "SELECT name, amount, start_date, end_date FROM states ORDER BY start_date ASC, id ASC;");
+		commands.insert("get open state", "SELECT name, amount, start_date, end_date FROM states WHERE name = :name AND end_date IS NULL;");
+		commands.insert("get later state", "SELECT name, amount, start_date, end_date FROM states WHERE name = :name AND end_date > :date ORDER BY end_date DESC LIMIT 1;");
+		commands.insert("get active states", "SELECT name, amount, start_date, end_date FROM states WHERE start_date <= :date AND (end_date IS NULL OR end_date > :date) ORDER BY start_date ASC, id ASC;");
+		commands.insert("find recommendation", "SELECT id FROM recommendations WHERE type = :type AND output = :output AND input = :input;");
+		commands.insert("update recommendation", "UPDATE recommendations SET lag = :lag, confidence = :confidence, stale = false WHERE id = :id;");
+		commands.insert("insert recommendation", "INSERT INTO recommendations (type, output, input, lag, confidence, hidden, stale) VALUES (:type, :output, :input, :lag, :confidence, false, false);");
+		commands.insert("mark recommendations stale", "UPDATE recommendations SET stale = true;");
+		commands.insert("get recommendations", "SELECT id, type, output, input, lag, confidence, hidden, stale FROM recommendations ORDER BY stale ASC, confidence DESC, id ASC;");
+		commands.insert("hide recommendation", "UPDATE recommendations SET hidden = true WHERE id = :id;");
+		commands.insert("count tables", "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'entries';");
+		commands.insert("backup database", "VACUUM INTO :path;");
+		commands.insert("copy merged entries", "INSERT INTO entries_rebuild (id, date ENTRY_COLUMNS) SELECT MAX(id), date ENTRY_VALUES FROM entries AS e GROUP BY date;");
+		commands.insert("latest answer", "(SELECT COLUMN FROM entries AS d WHERE d.date = e.date AND d.COLUMN IS NOT NULL ORDER BY d.id DESC LIMIT 1)");
+		commands.insert("create rebuilt states", "CREATE TABLE states_rebuild (id integer primary key, name text not null, amount real, start_date integer not null, end_date integer);");
+		commands.insert("copy rebuilt states", "INSERT INTO states_rebuild (id, name, amount, start_date, end_date) SELECT id, name, amount, CAST(julianday(start_date) + 0.5 AS INTEGER), CAST(julianday(end_date) + 0.5 AS INTEGER) FROM states;");
+		commands.insert("drop states", "DROP TABLE states;");
+		commands.insert("rename rebuilt states", "ALTER TABLE states_rebuild RENAME TO states;");
+		commands.insert("create tags", "CREATE TABLE IF NOT EXISTS tags (id integer primary key, name text not null unique);");
+		commands.insert("create entry tags", "CREATE TABLE IF NOT EXISTS entry_tags (date integer not null, tag_id integer not null, PRIMARY KEY (date, tag_id));");
+		commands.insert("get tag strings", "SELECT date, tags FROM entries WHERE tags IS NOT NULL;");
+		commands.insert("insert tag", "INSERT OR IGNORE INTO tags (name) VALUES (:name);");
+		commands.insert("tag entry", "INSERT OR IGNORE INTO entry_tags (date, tag_id) SELECT :date, id FROM tags WHERE name = :name;");
+		commands.insert("clear entry tags", "DELETE FROM entry_tags WHERE date = :date;");
+		commands.insert("get entry tags", "SELECT entry_tags.date, tags.name FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id ORDER BY entry_tags.rowid ASC;");
+		commands.insert("get entry tags for date", "SELECT entry_tags.date, tags.name FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id WHERE entry_tags.date = :date ORDER BY entry_tags.rowid ASC;");
+		commands.insert("get tag counts", "SELECT tags.name, COUNT(*) FROM tags JOIN entry_tags ON entry_tags.tag_id = tags.id GROUP BY tags.id ORDER BY COUNT(*) DESC, tags.name ASC;");
+		commands.insert("get recent tag counts", "SELECT tags.name, COUNT(*) FROM tags JOIN entry_tags ON entry_tags.tag_id = tags.id WHERE entry_tags.date >= :since GROUP BY tags.id ORDER BY COUNT(*) DESC, MAX(entry_tags.date) DESC, tags.name ASC LIMIT :limit;");
+		commands.insert("get tag dates", "SELECT entry_tags.date FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id WHERE tags.name = :name ORDER BY entry_tags.date ASC;");
+		commands.insert("get namespace dates", "SELECT DISTINCT entry_tags.date FROM entry_tags JOIN tags ON tags.id = entry_tags.tag_id WHERE substr(tags.name, 1, length(:prefix)) = :prefix ORDER BY entry_tags.date ASC;");
+		commands.insert("find tag", "SELECT id FROM tags WHERE name = :name;");
+		commands.insert("rename tag", "UPDATE tags SET name = :new WHERE id = :id;");
+		commands.insert("move tag entries", "UPDATE OR IGNORE entry_tags SET tag_id = :new WHERE tag_id = :old;");
+		commands.insert("untag entries", "DELETE FROM entry_tags WHERE tag_id = :id;");
+		commands.insert("delete tag", "DELETE FROM tags WHERE id = :id;");
+		commands.insert("create notes", "CREATE VIRTUAL TABLE IF NOT EXISTS notes USING fts5(body);");
+		commands.insert("clear note", "DELETE FROM notes WHERE rowid = :date;");
+		commands.insert("insert note", "INSERT INTO notes (rowid, body) VALUES (:date, :body);");
+		commands.insert("get notes", "SELECT rowid, body FROM notes;");
+		commands.insert("get note", "SELECT body FROM notes WHERE rowid = :date;");
+		commands.insert("search notes", "SELECT rowid, snippet(notes, 0, '[', ']', '...', 12) FROM notes WHERE notes MATCH :query ORDER BY rank, rowid DESC;");
+		commands.insert("rename tag recommendations", "UPDATE recommendations SET input = :new WHERE type = 'tag' AND input = :old;");
+		commands.insert("delete tag recommendations", "DELETE FROM recommendations WHERE type = 'tag' AND input = :old;");
+		commands.insert("add recommendation lags", "ALTER TABLE recommendations ADD COLUMN lag integer; ALTER TABLE recommendations ADD COLUMN stale boolean not null default false;");
+		commands.insert("get lagged recommendations", "SELECT id, type, output, input, hidden FROM recommendations WHERE type IN ('correlation', 'tag') ORDER BY confidence DESC, id ASC;");
+		commands.insert("set recommendation lag", "UPDATE recommendations SET input = :input, lag = :lag, hidden = :hidden WHERE id = :id;");
+		commands.insert("delete recommendation", "DELETE FROM recommendations WHERE id = :id;");
+		commands.insert("count entries", "SELECT COUNT (*) FROM entries WHERE COLUMN NOT NULL AND date >= :start AND date <= :end;");
 
-		Self {
+		Ok(Self {
     		conn,
 			commands
-		}
+		})
 	}
 
 	pub fn initialize_db(path: PathBuf) -> Result<Self, Error> {
@@ -46,18 +145,43 @@ impl DbHandler {
 				conn.execute(
 					"create table if not exists entries (
 						id integer primary key,
+						date integer not null unique
+					)",
+					[],
+				)?;
+
+				conn.execute(
+					"create table if not exists tags (
+						id integer primary key,
+						name text not null unique
+					)",
+					[],
+				)?;
+
+				conn.execute(
+					"create table if not exists entry_tags (
 						date integer not null,
-						tags text
+						tag_id integer not null,
+						primary key (date, tag_id)
 					)",
 					[],
 				)?;
+
+				// Notes are keyed by their entry's Julian date, kept as the rowid.
+				conn.execute("create virtual table if not exists notes using fts5(body)", [])?;
 		
 				conn.execute(
 					"create table if not exists fields (
 						name text not null unique,
 						category text not null,
 						type text not null,
-						active boolean not null
+						active boolean not null,
+						min real,
+						max real,
+						unit text,
+						step real,
+						description text,
+						choices text
 					)",
 					[],
 				)?;
@@ -68,8 +192,10 @@ impl DbHandler {
 						type text not null,
 						output text not null,
 						input text not null,
+						lag integer,
 						confidence real not null,
-						hidden bool not null
+						hidden bool not null,
+						stale bool not null default false
 					)",
 					[],
 				)?;
@@ -79,12 +205,14 @@ impl DbHandler {
 						id integer primary key,
 						name text not null,
 						amount real,
-						start_date text not null,
-						end_date text
+						start_date integer not null,
+						end_date integer
 					)", 
 					[],
 				)?;
 
+				conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
 				conn.execute("INSERT INTO fields (name, category, type, active) VALUES ('tags', 'i', 't', true);", [])?;
 			},
 			Err(err) => {
@@ -99,150 +227,746 @@ impl DbHandler {
 	}
 
 	pub fn insert_field(&self, field: &Field) -> Result<()> {
-		let (type_full, type_short) = match field.data_type {
-			FieldType::Numeric => ("real", "n"),
-			FieldType::Boolean => ("boolean", "b"),
-			FieldType::Text => ("text", "t"),
-		};
+		let tx = self.conn.unchecked_transaction()?;
+		self.write_field(field)?;
+		tx.commit()?;
+		Ok(())
+	}
+
+	/// Adds a field's column and its row in `fields`, leaving the caller to
+	/// hold a transaction around both.
+	fn write_field(&self, field: &Field) -> Result<()> {
+		if is_reserved(&field.name) {
+			return Err(anyhow!("The name {} is reserved", field.name))
+		}
+		if field.data_type == FieldType::Categorical && field.category == FieldCategory::Output {
+			return Err(anyhow!("Categorical fields cannot be outputs, as only numeric outputs are analysed"))
+		}
+		if field.data_type == FieldType::Text {
+			return Err(anyhow!("The {} field cannot hold text; tags are the only text field", field.name))
+		}
+		let (type_full, type_short) = type_codes(&field.data_type);
 		let category = match field.category {
 			FieldCategory::Input => "i",
 			FieldCategory::Output => "o",
 			FieldCategory::Hybrid => "h",
 		};
-		let ifc = self.commands.get("insert field column").unwrap().replace(":name", field.name.as_str()).replace(":type",type_full);
-		let ife = self.commands.get("insert field entry").unwrap().replace(":name", field.name.as_str()).replace(":type",type_short).replace(":category", category);
-
-		match self.conn.execute(&ifc, []) {
-			Ok(_) => {
-				match self.conn.execute(&ife, []) {
-					Ok(_) => return Ok(()),
-					Err(e) => return Err(anyhow::Error::new(e).context("Failed inserting row")),
-				}
-			},
-			Err(e) => return Err(anyhow::Error::new(e).context("Failed inserting column")),
+		let ifc = self.commands.get("insert field column").unwrap()
+			.replace("FIELD_TYPE", type_full)
+			.replace("FIELD_NAME", &quote_identifier(&field.name)?);
+		let ife = self.commands.get("insert field entry").unwrap();
+
+		if let Err(e) = self.conn.execute(&ifc, []) {
+			return Err(anyhow::Error::new(e).context("Failed inserting column"))
 		}
+		let params = named_params! {
+			":name": field.name,
+			":category": category,
+			":type": type_short,
+			":min": field.meta.min,
+			":max": field.meta.max,
+			":unit": field.meta.unit,
+			":step": field.meta.step,
+			":description": field.meta.description,
+			":choices": choices_column(&field.meta),
+		};
+		if let Err(e) = self.conn.execute(ife, params) {
+			return Err(anyhow::Error::new(e).context("Failed inserting row"))
+		}
+		Ok(())
 	}
 
-	//TODO: Change to get_active_fields?
+	/// Returns the fields that are currently being tracked.
 	pub fn get_fields(&self) -> Result<Vec<Field>, Error> {
 		let mut stmt = self.conn.prepare(self.commands.get("get active fields").unwrap())?;
-		let fields = stmt.query_map([], |row| {
-			let c1: String = row.get(1)?;
-			let category = match c1.as_str() {
-				"h" => FieldCategory::Hybrid,
-				"o" => FieldCategory::Output,
-				_ => FieldCategory::Input,
-			};
+		let fields = stmt.query_map([], field_from_row)?;
 
-			let c2: String = row.get(2)?;
-			let data_type = match c2.as_str() {
-				"n" => FieldType::Numeric,
-				"b" => FieldType::Boolean,
-				_ => FieldType::Text,
-			};
+		fields.collect()
+	}
 
-			Ok(Field {
-				name: row.get(0)?,
-				category,
-				data_type,
-				active: row.get(3)?,
-			})
-		})?;
+	/// Returns every field, including deactivated ones whose columns still
+	/// hold past data.
+	pub fn get_all_fields(&self) -> Result<Vec<Field>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get fields").unwrap())?;
+		let fields = stmt.query_map([], field_from_row)?;
 
 		fields.collect()
 	}
 
+	/// Replaces a field's unit, range, step, description and choices.
+	/// Returns false if there is no field with that name.
+	pub fn set_field_meta(&self, name: &str, meta: &FieldMeta) -> Result<bool, Error> {
+		let changed = self.conn.execute(self.commands.get("set field meta").unwrap(), named_params! {
+			":name": name,
+			":min": meta.min,
+			":max": meta.max,
+			":unit": meta.unit,
+			":step": meta.step,
+			":description": meta.description,
+			":choices": choices_column(meta),
+		})?;
+		Ok(changed > 0)
+	}
+
+	/// Starts or stops prompting for a field. Its column and past values are
+	/// kept either way. Returns false if there is no field with that name.
+	pub fn set_field_active(&self, name: &str, active: bool) -> Result<bool, Error> {
+		let changed = self.conn.execute(self.commands.get("set field active").unwrap(), named_params! {":name": name, ":active": active})?;
+		Ok(changed > 0)
+	}
+
+	/// Renames a field, rebuilding `entries` so that its column follows, and
+	/// points saved recommendations at the new name.
+	pub fn rename_field(&self, old: &str, new: &str) -> Result<()> {
+		let fields = self.get_all_fields()?;
+		let field = match fields.iter().find(|f| f.name == old) {
+			Some(field) => field,
+			None => return Err(anyhow!("There is no field named {}", old)),
+		};
+		if field.data_type == FieldType::Text {
+			return Err(anyhow!("The {} field cannot be renamed", old))
+		}
+		// Column names are case-insensitive, so `Mood` would clash with `mood`.
+		let taken = fields.iter().any(|f| f.name != old && f.name.eq_ignore_ascii_case(new));
+		if taken || is_reserved(new) {
+			return Err(anyhow!("The name {} is already in use", new))
+		}
+		quote_identifier(new)?;
+
+		let tx = self.conn.unchecked_transaction()?;
+		self.rebuild_entries(old, new, type_codes(&field.data_type).0, "COLUMN")?;
+		let names = named_params! {":old": old, ":new": new};
+		tx.execute(self.commands.get("rename field entry").unwrap(), names)?;
+		tx.execute(self.commands.get("rename field recommendations").unwrap(), names)?;
+		tx.execute(self.commands.get("rename field recommendation inputs").unwrap(), names)?;
+		tx.commit()?;
+
+		Ok(())
+	}
+
+	/// Changes a field between numeric and boolean, converting its values:
+	/// true and false become 1 and 0, and 0 and 1 become false and true.
+	/// Any other numeric value would be lost, so such conversions are refused
+	/// unless `force` is set, in which case every non-zero value becomes
+	/// true. Returns the number of values that were collapsed this way.
+	pub fn convert_field(&self, name: &str, to: FieldType, force: bool) -> Result<u32> {
+		let field = match self.get_all_fields()?.into_iter().find(|f| f.name == name) {
+			Some(field) => field,
+			None => return Err(anyhow!("There is no field named {}", name)),
+		};
+		let column = quote_identifier(name)?;
+		let (value, lossy) = match (&field.data_type, &to) {
+			(from, to) if from == to => return Err(anyhow!("{} is already {:?}", name, to)),
+			(FieldType::Boolean, FieldType::Numeric) => ("CAST(COLUMN AS REAL)", 0),
+			(FieldType::Numeric, FieldType::Boolean) => {
+				let count = self.commands.get("count non binary values").unwrap().replace("COLUMN", &column);
+				let lossy = self.conn.query_row(&count, [], |row| row.get(0))?;
+				if lossy > 0 && !force {
+					return Err(anyhow!("{} values of {} are neither 0 nor 1 and would be lost; convert with force to treat them as true", lossy, name))
+				}
+				("CASE WHEN COLUMN IS NULL THEN NULL WHEN COLUMN = 0 THEN 0 ELSE 1 END", lossy)
+			},
+			(from, to) => return Err(anyhow!("Cannot convert {} from {:?} to {:?}", name, from, to)),
+		};
+
+		let (type_full, type_short) = type_codes(&to);
+		let tx = self.conn.unchecked_transaction()?;
+		self.rebuild_entries(name, name, type_full, value)?;
+		tx.execute(self.commands.get("set field type").unwrap(), named_params! {":name": name, ":type": type_short})?;
+		tx.commit()?;
+
+		Ok(lossy)
+	}
+
+	/// Recreates `entries` with `column` renamed to `new_name`, declared as
+	/// `new_type` and filled from `value`, an expression in which `COLUMN`
+	/// stands for the old column. Every other column is copied as is. SQLite
+	/// cannot change a column's type in place, hence the copy; callers should
+	/// hold a transaction so a failure leaves the original table intact.
+	fn rebuild_entries(&self, column: &str, new_name: &str, new_type: &str, value: &str) -> Result<(), Error> {
+		let mut definitions = Vec::new();
+		let mut names = Vec::new();
+		let mut values = Vec::new();
+		for (name, declared_type) in self.entry_columns()? {
+			let quoted = quote_identifier(&name)?;
+			if name == column {
+				let new_quoted = quote_identifier(new_name)?;
+				definitions.push(format!("{} {}", new_quoted, new_type));
+				names.push(new_quoted);
+				values.push(value.replace("COLUMN", &quoted));
+			} else {
+				definitions.push(format!("{} {}", quoted, declared_type));
+				names.push(quoted.clone());
+				values.push(quoted);
+			}
+		}
+
+		self.replace_entries(&definitions, &names, &values, "copy rebuilt entries")
+	}
+
+	/// The name and declared type of every column of `entries` other than
+	/// `id` and `date`.
+	fn entry_columns(&self) -> Result<Vec<(String, String)>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get entry columns").unwrap())?;
+		let columns = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+		columns.collect()
+	}
+
+	/// Swaps `entries` for a new table with the given column definitions,
+	/// filled by the `copy` command from `values`. `id` and `date` are always
+	/// kept, so the lists only cover the columns after them and may be empty.
+	fn replace_entries(&self, definitions: &[String], names: &[String], values: &[String], copy: &str) -> Result<(), Error> {
+		let following = |items: &[String]| items.iter().map(|item| format!(", {}", item)).collect::<String>();
+		let create = self.commands.get("create rebuilt entries").unwrap().replace("ENTRY_COLUMNS", &following(definitions));
+		let copy = self.commands.get(copy).unwrap()
+			.replace("ENTRY_COLUMNS", &following(names))
+			.replace("ENTRY_VALUES", &following(values));
+		self.conn.execute(&create, [])?;
+		self.conn.execute(&copy, [])?;
+		self.conn.execute(self.commands.get("drop entries").unwrap(), [])?;
+		self.conn.execute(self.commands.get("rename rebuilt entries").unwrap(), [])?;
+
+		Ok(())
+	}
+
+	fn schema_version(&self) -> Result<u32, Error> {
+		self.conn.pragma_query_value(None, "user_version", |row| row.get(0))
+	}
+
+	/// Applies any migrations the database is missing, each in its own
+	/// transaction. The database is first copied next to the original, so
+	/// returns the copy's location if there was anything to do. Empty files
+	/// are left alone for `initialize_db` to fill in.
+	pub fn migrate(&self, path: &Path) -> Result<Option<PathBuf>> {
+		let version = self.schema_version()?;
+		if version > SCHEMA_VERSION {
+			return Err(anyhow!("The database is at schema version {}, but this version of mood only understands up to {}", version, SCHEMA_VERSION))
+		}
+		let tables: u32 = self.conn.query_row(self.commands.get("count tables").unwrap(), [], |row| row.get(0))?;
+		if version == SCHEMA_VERSION || tables == 0 {
+			return Ok(None)
+		}
+
+		let backup = backup_path(path, version);
+		let backup_str = backup.to_string_lossy();
+		self.conn.execute(self.commands.get("backup database").unwrap(), &[(":path", &backup_str)])
+			.context("Failed backing up the database")?;
+
+		for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+			let tx = self.conn.unchecked_transaction()?;
+			migration(self).with_context(|| format!("Failed migrating to schema version {}", index + 1))?;
+			tx.pragma_update(None, "user_version", index as u32 + 1)?;
+			tx.commit()?;
+		}
+
+		Ok(Some(backup))
+	}
+
+	/// Version 1: one entry per day. Older databases allowed several entries
+	/// for a date; they are merged, with the newest answer to each field
+	/// winning.
+	fn migrate_unique_dates(&self) -> Result<(), Error> {
+		let mut definitions = Vec::new();
+		let mut names = Vec::new();
+		let mut values = Vec::new();
+		for (name, declared_type) in self.entry_columns()? {
+			let quoted = quote_identifier(&name)?;
+			definitions.push(format!("{} {}", quoted, declared_type));
+			values.push(self.commands.get("latest answer").unwrap().replace("COLUMN", &quoted));
+			names.push(quoted);
+		}
+
+		self.replace_entries(&definitions, &names, &values, "copy merged entries")
+	}
+
+	/// Version 2: state dates are stored as Julian days, like entry dates,
+	/// rather than as ISO text.
+	fn migrate_state_dates(&self) -> Result<(), Error> {
+		self.conn.execute(self.commands.get("create rebuilt states").unwrap(), [])?;
+		self.conn.execute(self.commands.get("copy rebuilt states").unwrap(), [])?;
+		self.conn.execute(self.commands.get("drop states").unwrap(), [])?;
+		self.conn.execute(self.commands.get("rename rebuilt states").unwrap(), [])?;
+
+		Ok(())
+	}
+
+	/// Version 3: tags move from a space-separated `entries.tags` column to
+	/// the `tags` and `entry_tags` tables.
+	fn migrate_tag_table(&self) -> Result<(), Error> {
+		self.conn.execute(self.commands.get("create tags").unwrap(), [])?;
+		self.conn.execute(self.commands.get("create entry tags").unwrap(), [])?;
+
+		let mut stmt = self.conn.prepare(self.commands.get("get tag strings").unwrap())?;
+		let tagged = stmt
+			.query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)))?
+			.collect::<Result<Vec<(i32, String)>, Error>>()?;
+		for (date, tags) in tagged {
+			let tags: Vec<String> = tags.split(' ').map(String::from).collect();
+			self.write_tags(date, &tags)?;
+		}
+
+		let mut definitions = Vec::new();
+		let mut names = Vec::new();
+		for (name, declared_type) in self.entry_columns()?.into_iter().filter(|(name, _)| name != "tags") {
+			let quoted = quote_identifier(&name)?;
+			definitions.push(format!("{} {}", quoted, declared_type));
+			names.push(quoted);
+		}
+
+		self.replace_entries(&definitions, &names, &names, "copy rebuilt entries")
+	}
+
+	/// Version 4: fields gain an optional range, unit, step and description.
+	fn migrate_field_meta(&self) -> Result<(), Error> {
+		self.conn.execute_batch(self.commands.get("add field meta").unwrap())
+	}
+
+	/// Version 5: categorical fields store their list of answers.
+	fn migrate_field_choices(&self) -> Result<(), Error> {
+		self.conn.execute_batch(self.commands.get("add field choices").unwrap())
+	}
+
+	/// Version 6: entries can carry a note, kept in a full-text index.
+	fn migrate_notes(&self) -> Result<(), Error> {
+		self.conn.execute(self.commands.get("create notes").unwrap(), [])?;
+
+		Ok(())
+	}
+
+	/// Version 7: field columns are declared with their field's type. Earlier
+	/// versions put the field's name in front of the type, so a name such as
+	/// `context` or `points` gave the column the wrong affinity; copying the
+	/// values across converts them back.
+	fn migrate_column_types(&self) -> Result<(), Error> {
+		let types: HashMap<String, FieldType> = self.get_all_fields()?.into_iter().map(|f| (f.name, f.data_type)).collect();
+		let mut definitions = Vec::new();
+		let mut names = Vec::new();
+		for (name, declared_type) in self.entry_columns()? {
+			let quoted = quote_identifier(&name)?;
+			let declared_type = match types.get(&name) {
+				Some(data_type) => type_codes(data_type).0,
+				None => declared_type.as_str(),
+			};
+			definitions.push(format!("{} {}", quoted, declared_type));
+			names.push(quoted);
+		}
+
+		self.replace_entries(&definitions, &names, &names, "copy rebuilt entries")
+	}
+
+	/// Version 8: recommendations keep their lag in a column of its own
+	/// rather than in the input (`sleep+1d`), so that a finding whose
+	/// strongest lag moves is still the same finding. Of the findings that
+	/// differed only in lag, the most confident is kept, hidden if any was.
+	/// Recommendations can also be marked stale.
+	fn migrate_recommendation_lags(&self) -> Result<(), Error> {
+		self.conn.execute_batch(self.commands.get("add recommendation lags").unwrap())?;
+
+		let mut stmt = self.conn.prepare(self.commands.get("get lagged recommendations").unwrap())?;
+		let lagged = stmt
+			.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))?
+			.collect::<Result<Vec<(i64, String, String, String, bool)>, Error>>()?;
+		// Type, output and input.
+		type Identity = (String, String, String);
+		let mut kept: HashMap<Identity, (i64, Option<u32>, bool)> = HashMap::new();
+		for (id, kind, output, input, hidden) in lagged {
+			let split = input.rsplit_once('+')
+				.and_then(|(name, lag)| Some((name, lag.strip_suffix('d')?.parse::<u32>().ok()?)));
+			let (input, lag) = match split {
+				Some((name, lag)) => (String::from(name), Some(lag)),
+				None => (input, None),
+			};
+			match kept.get_mut(&(kind.clone(), output.clone(), input.clone())) {
+				Some(strongest) => {
+					strongest.2 |= hidden;
+					self.conn.execute(self.commands.get("delete recommendation").unwrap(), &[(":id", &id)])?;
+				},
+				None => { kept.insert((kind, output, input), (id, lag, hidden)); },
+			}
+		}
+		for ((_, _, input), (id, lag, hidden)) in kept {
+			self.conn.execute(self.commands.get("set recommendation lag").unwrap(), named_params! {":input": input, ":lag": lag, ":hidden": hidden, ":id": id})?;
+		}
+
+		Ok(())
+	}
+
+	/// Returns every entry, including values for deactivated fields.
 	pub fn get_entries(&self) -> Result<Vec<Entry>, Error> {
-		let fields = self.get_fields()?;
+		let fields = self.get_all_fields()?;
 		let ge = self.commands.get("get entries").unwrap();
-		let field_columns: Vec<String> = fields.iter().map(|f| f.name.clone()).collect();
-		let ge = ge.replace("ENTRY_COLUMNS", field_columns.join(", ").as_str());
+		let ge = ge.replace("ENTRY_COLUMNS", &column_list(&fields)?);
 		let mut stmt = self.conn.prepare(&ge)?;
 		
-		let entries = stmt.query_map([], |row| {
-			let mut i = 0;
-			let mut numeric_fields = HashMap::new();
-			let mut boolean_fields = HashMap::new();
-			let mut tags = Vec::new();
-
-			// println!("Before the loop");
-			let date: i32 = row.get(i)?;
-			i += 1;
-			while i < 1+field_columns.len() {
-				let field = fields.get(i-1).unwrap();
-				// println!("Loop iteration {}, looking at field {}", i, field.name);				
-				match field.data_type {
-					FieldType::Numeric => {
-						let val: f64 = row.get(i)?;
-						numeric_fields.insert(field.name.clone(), val);
-					},
-					FieldType::Boolean => {
-						let val: bool = row.get(i)?;
-						boolean_fields.insert(field.name.clone(), val);
-					},
-					FieldType::Text => {
-						let tag_col: String = row.get(i)?;
-						tags = tag_col.split(" ").map(|s| String::from(s)).collect();
-					},
-				}
-				i += 1;
-			}
+		let entries = stmt.query_map([], |row| entry_from_row(row, &fields))?;
+		let mut entries = entries.collect::<Result<Vec<Entry>, Error>>()?;
 
-			Ok(Entry {
-				date: Date::from_julian_day(date).unwrap(),
-				numeric_fields,
-				boolean_fields,
-				tags,
-			})
+		let mut stmt = self.conn.prepare(self.commands.get("get entry tags").unwrap())?;
+		let mut tags: HashMap<i32, Vec<String>> = HashMap::new();
+		for tag in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+			let (date, name) = tag?;
+			tags.entry(date).or_default().push(name);
+		}
+		let mut stmt = self.conn.prepare(self.commands.get("get notes").unwrap())?;
+		let mut notes: HashMap<i32, String> = HashMap::new();
+		for note in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+			let (date, body) = note?;
+			notes.insert(date, body);
+		}
+		for entry in entries.iter_mut() {
+			entry.tags = tags.remove(&entry.date.to_julian_day()).unwrap_or_default();
+			entry.note = notes.remove(&entry.date.to_julian_day());
+		}
+
+		Ok(entries)
+	}
+
+	pub fn get_entry(&self, date: Date) -> Result<Option<Entry>, Error> {
+		let fields = self.get_all_fields()?;
+		let ge = self.commands.get("get entry").unwrap();
+		let ge = ge.replace("ENTRY_COLUMNS", &column_list(&fields)?);
+		let mut stmt = self.conn.prepare(&ge)?;
+
+		let mut entries = stmt.query_map(&[(":date", &date.to_julian_day())], |row| entry_from_row(row, &fields))?;
+		let mut entry = match entries.next().transpose()? {
+			Some(entry) => entry,
+			None => return Ok(None),
+		};
+
+		let mut stmt = self.conn.prepare(self.commands.get("get entry tags for date").unwrap())?;
+		let tags = stmt.query_map(&[(":date", &date.to_julian_day())], |row| row.get(1))?;
+		entry.tags = tags.collect::<Result<Vec<String>, Error>>()?;
+		entry.note = self.get_note(date)?;
+
+		Ok(Some(entry))
+	}
+
+	pub fn get_note(&self, date: Date) -> Result<Option<String>, Error> {
+		self.conn.query_row(self.commands.get("get note").unwrap(), &[(":date", &date.to_julian_day())], |row| row.get(0))
+			.optional()
+	}
+
+	/// Searches notes with an FTS5 query, such as `migraine` or `head*`,
+	/// returning the date of each match with the matching words in
+	/// brackets. The best matches come first.
+	pub fn search_notes(&self, query: &str) -> Result<Vec<(Date, String)>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("search notes").unwrap())?;
+		let matches = stmt.query_map(&[(":query", &query)], |row| {
+			let date: i32 = row.get(0)?;
+			let date = Date::from_julian_day(date).map_err(|e| Error::FromSqlConversionFailure(0, Type::Integer, e.into()))?;
+			Ok((date, row.get(1)?))
 		})?;
 
-		entries.collect()
+		matches.collect()
 	}
 
-	pub fn get_numeric_values(&self, field: &String) -> Result<Vec<(u32, f64)>> {
-		let s = self.commands.get("get dates and numeric field entries").unwrap();
-		let s = s.replace(":column", field);
+	/// Returns every tag in use with the number of days it was recorded on,
+	/// most used first.
+	pub fn get_tags(&self) -> Result<Vec<(String, u32)>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get tag counts").unwrap())?;
+		let tags = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
 
-		let mut statement = self.conn.prepare(&s).unwrap();
-		let a = statement
-			.query_map([], 
-			|row| Ok((row.get(0).unwrap(), row.get(1).unwrap())))? //We're writing Lisp code now!
-			.map(|v| v.unwrap()).collect();
-		Ok(a)
+		tags.collect()
 	}
 
-	pub fn get_numeric_values_between_dates(&self, field: &String, start: u32, end: u32) -> Result<Vec<(u32, f64)>> {
-		let s = self.commands.get("get dates and numeric field entries between dates").unwrap();
-		let s = s.replace(":column", field);
-		
-		let mut statement = self.conn.prepare(&s).unwrap();
+	/// Returns up to `limit` of the tags recorded since `since`, with how many
+	/// days each was recorded on, most used first.
+	pub fn get_recent_tags(&self, since: Date, limit: u32) -> Result<Vec<(String, u32)>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get recent tag counts").unwrap())?;
+		let tags = stmt.query_map(named_params! {":since": since.to_julian_day(), ":limit": limit}, |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+		tags.collect()
+	}
+
+	/// Returns the days on which `tag` was recorded, earliest first.
+	pub fn get_tag_dates(&self, tag: &str) -> Result<Vec<Date>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get tag dates").unwrap())?;
+		let dates = stmt.query_map(&[(":name", &tag)], |row| {
+			let date: i32 = row.get(0)?;
+			Date::from_julian_day(date).map_err(|e| Error::FromSqlConversionFailure(0, Type::Integer, e.into()))
+		})?;
+
+		dates.collect()
+	}
+
+	/// Returns every namespace used by a tag, including nested ones, in
+	/// alphabetical order.
+	pub fn get_namespaces(&self) -> Result<Vec<String>, Error> {
+		let mut namespaces: Vec<String> = self.get_tags()?
+			.iter()
+			.flat_map(|(tag, _)| tag_namespaces(tag).into_iter().map(String::from).collect::<Vec<String>>())
+			.collect();
+		namespaces.sort();
+		namespaces.dedup();
+
+		Ok(namespaces)
+	}
+
+	/// Returns the days on which any tag in `namespace` was recorded,
+	/// earliest first.
+	pub fn get_namespace_dates(&self, namespace: &str) -> Result<Vec<Date>, Error> {
+		let prefix = format!("{}:", namespace);
+		let mut stmt = self.conn.prepare(self.commands.get("get namespace dates").unwrap())?;
+		let dates = stmt.query_map(&[(":prefix", &prefix)], |row| {
+			let date: i32 = row.get(0)?;
+			Date::from_julian_day(date).map_err(|e| Error::FromSqlConversionFailure(0, Type::Integer, e.into()))
+		})?;
+
+		dates.collect()
+	}
+
+	/// Renames a tag on every day it was recorded. If `new` is already in use
+	/// the two tags are merged, so that `spoke:diego` and `talked:diego` can
+	/// be folded into one.
+	pub fn rename_tag(&self, old: &str, new: &str) -> Result<TagRename> {
+		let new = new.trim();
+		if new.is_empty() {
+			return Err(anyhow!("Tags cannot be empty"))
+		}
+		let find = |name: &str| self.conn
+			.query_row(self.commands.get("find tag").unwrap(), &[(":name", &name)], |row| row.get::<_, i64>(0))
+			.optional();
+		let old_id = match find(old)? {
+			Some(id) => id,
+			None => return Err(anyhow!("There is no tag named {}", old)),
+		};
+		let names = named_params! {":old": old, ":new": new};
+
+		let tx = self.conn.unchecked_transaction()?;
+		let renamed = match find(new)? {
+			Some(new_id) if new_id == old_id => return Ok(TagRename::Renamed),
+			Some(new_id) => {
+				tx.execute(self.commands.get("move tag entries").unwrap(), named_params! {":old": old_id, ":new": new_id})?;
+				tx.execute(self.commands.get("untag entries").unwrap(), &[(":id", &old_id)])?;
+				tx.execute(self.commands.get("delete tag").unwrap(), &[(":id", &old_id)])?;
+				// The merged tag is a new finding, to be picked up on the next analysis.
+				tx.execute(self.commands.get("delete tag recommendations").unwrap(), &[(":old", &old)])?;
+				TagRename::Merged
+			},
+			None => {
+				tx.execute(self.commands.get("rename tag").unwrap(), named_params! {":id": old_id, ":new": new})?;
+				tx.execute(self.commands.get("rename tag recommendations").unwrap(), names)?;
+				TagRename::Renamed
+			},
+		};
+		tx.commit()?;
+
+		Ok(renamed)
+	}
+
+	/// Sets or clears the note for `date`. Blank notes are not kept.
+	fn write_note(&self, date: i32, note: &Option<String>) -> Result<(), Error> {
+		self.conn.execute(self.commands.get("clear note").unwrap(), &[(":date", &date)])?;
+		if let Some(body) = note.as_deref().map(str::trim).filter(|b| !b.is_empty()) {
+			self.conn.execute(self.commands.get("insert note").unwrap(), named_params! {":date": date, ":body": body})?;
+		}
+
+		Ok(())
+	}
+
+	/// Sets the tags recorded on `date`, creating any that are new.
+	fn write_tags(&self, date: i32, tags: &[String]) -> Result<(), Error> {
+		self.conn.execute(self.commands.get("clear entry tags").unwrap(), &[(":date", &date)])?;
+		for tag in tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+			self.conn.execute(self.commands.get("insert tag").unwrap(), &[(":name", &tag)])?;
+			self.conn.execute(self.commands.get("tag entry").unwrap(), named_params! {":date": date, ":name": tag})?;
+		}
+
+		Ok(())
+	}
+
+	/// Returns the (Julian date, value) pairs for a numeric field, skipping
+	/// days on which it was left unanswered. Times of day are unwrapped
+	/// around their mean so that they can be analysed as plain numbers.
+	pub fn get_numeric_values(&self, field: &str) -> Result<Vec<(u32, f64)>> {
+		let values = self.read_numeric_values(field, "get dates and numeric field entries", &[])?;
+		self.unwrap_times(field, values)
+	}
+
+	pub fn get_numeric_values_between_dates(&self, field: &str, start: u32, end: u32) -> Result<Vec<(u32, f64)>> {
+		let range: [(&str, &dyn ToSql); 2] = [(":start", &start), (":end", &end)];
+		let values = self.read_numeric_values(field, "get dates and numeric field entries between dates", &range)?;
+		self.unwrap_times(field, values)
+	}
+
+	fn read_numeric_values(&self, field: &str, command: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<(u32, f64)>> {
+		let s = self.commands.get(command).unwrap();
+		let s = s.replace("COLUMN", &quote_identifier(field)?);
+
+		let mut statement = self.conn.prepare(&s)?;
 		let a = statement
-			.query_map(&[(":start", &start.to_string()), (":end", &end.to_string())], 
-			|row| Ok((row.get(0).unwrap(), row.get(1).unwrap())))? //We're writing Lisp code now!
-			.map(|v| v.unwrap()).collect();
-		// let a = s.mapped(|row| Ok((row.get(0).unwrap(), row.get(1).unwrap()))).map(|v| v.unwrap()).collect();
+			.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?
+			.collect::<Result<_, Error>>()?;
 		Ok(a)
 	}
 
+	/// Moves the values of a time of day field to within twelve hours of the
+	/// field's overall mean, so that 23:50 and 00:10 stay twenty minutes
+	/// apart. Values of any other field are returned unchanged.
+	fn unwrap_times(&self, field: &str, mut values: Vec<(u32, f64)>) -> Result<Vec<(u32, f64)>> {
+		let fields = self.get_all_fields()?;
+		if !fields.iter().any(|f| f.name == field && f.data_type == FieldType::TimeOfDay) {
+			return Ok(values)
+		}
+
+		let all = self.read_numeric_values(field, "get dates and numeric field entries", &[])?;
+		let centre = mean_time_of_day(&all.iter().map(|(_, time)| *time).collect::<Vec<f64>>());
+		for value in values.iter_mut() {
+			value.1 = unwrap_time_of_day(value.1, centre);
+		}
+
+		Ok(values)
+	}
+
 	pub fn insert_entry(&self, entry: &Entry) -> Result<usize, Error> {
-		// println!("Inserting row");
+		let tx = self.conn.unchecked_transaction()?;
+		let inserted = self.write_entry(entry)?;
+		tx.commit()?;
+
+		Ok(inserted)
+	}
+
+	/// Inserts an entry and its tags, leaving the caller to hold a
+	/// transaction around both.
+	fn write_entry(&self, entry: &Entry) -> Result<usize, Error> {
 		let ie = self.commands.get("insert entry").unwrap();
-		let mut cols = String::new();
-		let mut values = format!("{}, ", entry.date.to_julian_day());
+		let julian_day = entry.date.to_julian_day();
+		let mut cols = vec![String::from("date")];
+		let mut values: Vec<&dyn ToSql> = vec![&julian_day];
 		for (key, value) in &entry.boolean_fields {
-			cols.push_str(format!("'{}', ", key).as_str());
-			values.push_str(format!("{}, ", value).as_str());
+			cols.push(quote_identifier(key)?);
+			values.push(value);
 		}
 		for (key, value) in &entry.numeric_fields {
-			cols.push_str(format!("{}, ", key).as_str());
-			values.push_str(format!("{}, ", value).as_str());
+			cols.push(quote_identifier(key)?);
+			values.push(value);
+		}
+		for (key, value) in &entry.categorical_fields {
+			cols.push(quote_identifier(key)?);
+			values.push(value);
 		}
-		cols.push_str(format!("tags").as_str());
-		values.push_str(format!("'{}'", entry.tags.join(" ")).as_str());
 
-		let ie = ie.replace("ENTRY_COLUMNS", cols.as_str()).replace("ENTRY_VALUES", values.as_str());
-		// println!("Inserting row: {}", ie);
-		self.conn.execute(&ie, [])
+		let placeholders = vec!["?"; values.len()].join(", ");
+		let ie = ie.replace("ENTRY_COLUMNS", &cols.join(", ")).replace("ENTRY_VALUES", &placeholders);
+		let inserted = self.conn.execute(&ie, values.as_slice())?;
+		self.write_tags(julian_day, &entry.tags)?;
+		self.write_note(julian_day, &entry.note)?;
+
+		Ok(inserted)
+	}
+
+	pub fn update_entry(&self, entry: &Entry) -> Result<usize, Error> {
+		let tx = self.conn.unchecked_transaction()?;
+		let updated = self.write_update(entry)?;
+		tx.commit()?;
+
+		Ok(updated)
+	}
+
+	/// Updates an entry and its tags and note, leaving the caller to hold a
+	/// transaction around them.
+	fn write_update(&self, entry: &Entry) -> Result<usize, Error> {
+		let ue = self.commands.get("update entry").unwrap();
+		let julian_day = entry.date.to_julian_day();
+		// Assigning the date to itself keeps the statement valid, and the
+		// count of updated rows accurate, when only tags were answered.
+		let mut assignments = vec![String::from("date = ?")];
+		let mut values: Vec<&dyn ToSql> = vec![&julian_day];
+		for (key, value) in &entry.boolean_fields {
+			assignments.push(format!("{} = ?", quote_identifier(key)?));
+			values.push(value);
+		}
+		for (key, value) in &entry.numeric_fields {
+			assignments.push(format!("{} = ?", quote_identifier(key)?));
+			values.push(value);
+		}
+		for (key, value) in &entry.categorical_fields {
+			assignments.push(format!("{} = ?", quote_identifier(key)?));
+			values.push(value);
+		}
+		values.push(&julian_day);
+
+		let ue = ue.replace("ENTRY_ASSIGNMENTS", &assignments.join(", "));
+		let updated = self.conn.execute(&ue, values.as_slice())?;
+		if updated > 0 {
+			self.write_tags(julian_day, &entry.tags)?;
+			self.write_note(julian_day, &entry.note)?;
+		}
+
+		Ok(updated)
+	}
+
+	/// Adds `fields` and writes `entries` in a single transaction, merging
+	/// each entry into any that already exists for its date. Nothing is
+	/// written if any part fails. Returns the number of entries inserted
+	/// and merged.
+	pub fn import_entries(&self, fields: &[Field], entries: &[Entry]) -> Result<(usize, usize)> {
+		let tx = self.conn.unchecked_transaction()?;
+		for field in fields {
+			self.write_field(field).with_context(|| format!("Failed adding field {}", field.name))?;
+		}
+		let (mut inserted, mut merged) = (0, 0);
+		for entry in entries {
+			let written = match self.get_entry(entry.date)? {
+				Some(mut existing) => {
+					existing.merge(entry);
+					merged += 1;
+					self.write_update(&existing)
+				},
+				None => {
+					inserted += 1;
+					self.write_entry(entry)
+				},
+			};
+			written.with_context(|| format!("Failed writing the entry for {}", entry.date))?;
+		}
+		tx.commit()?;
+
+		Ok((inserted, merged))
+	}
+
+	/// Writes `entry`, resolving a clash with an existing entry for the
+	/// same date according to `on_conflict`.
+	pub fn upsert_entry(&self, entry: &Entry, on_conflict: EntryConflict) -> Result<EntryWrite, Error> {
+		let existing = match self.get_entry(entry.date)? {
+			Some(existing) => existing,
+			None => {
+				self.insert_entry(entry)?;
+				return Ok(EntryWrite::Inserted)
+			},
+		};
+
+		match on_conflict {
+			EntryConflict::Abort => Ok(EntryWrite::Aborted),
+			EntryConflict::Merge => {
+				let mut merged = existing;
+				merged.merge(entry);
+				self.update_entry(&merged)?;
+				Ok(EntryWrite::Merged)
+			},
+			EntryConflict::Replace => {
+				// Deactivated fields are never prompted for, so their values
+				// survive the replacement.
+				let mut replacement = entry.clone();
+				for field in self.get_all_fields()?.iter().filter(|f| !f.active) {
+					if let Some(value) = existing.numeric_fields.get(&field.name) {
+						replacement.numeric_fields.insert(field.name.clone(), *value);
+					}
+					if let Some(value) = existing.boolean_fields.get(&field.name) {
+						replacement.boolean_fields.insert(field.name.clone(), *value);
+					}
+					if let Some(value) = existing.categorical_fields.get(&field.name) {
+						replacement.categorical_fields.insert(field.name.clone(), value.clone());
+					}
+				}
+				let tx = self.conn.unchecked_transaction()?;
+				tx.execute(self.commands.get("delete entry").unwrap(), &[(":date", &entry.date.to_julian_day())])?;
+				self.write_entry(&replacement)?;
+				tx.commit()?;
+				Ok(EntryWrite::Replaced)
+			},
+		}
 	}
 
 	pub fn get_range(&self) -> (u32, u32) {
@@ -257,13 +981,307 @@ impl DbHandler {
 		(first, last)
 	}
 
-	pub fn count_entries(&self, field: &String, start: u32, end: u32) -> Result<u32, Error> {
-		Ok(self.conn.query_row(
-			self.commands.get("count entries").unwrap(), 
-			&[(":column", field), (":start", &start.to_string()), (":end", &end.to_string())],
-		| row | row.get(0))?)
+	/// Counts the days between `start` and `end` on which `field` was
+	/// answered; always agrees with `get_numeric_values_between_dates`.
+	pub fn count_entries(&self, field: &str, start: u32, end: u32) -> Result<u32, Error> {
+		let ce = self.commands.get("count entries").unwrap();
+		let ce = ce.replace("COLUMN", &quote_identifier(field)?);
+		self.conn.query_row(
+			&ce,
+			&[(":start", &start), (":end", &end)],
+		| row | row.get(0))
+	}
+
+
+	/// Opens the state `name` on `date`, or closes it if it is already open.
+	/// Toggling an open state with a different `amount` records the change
+	/// by closing the current interval and opening a new one. A state cannot
+	/// be opened on a day an earlier interval of it was still in effect.
+	pub fn toggle_state(&self, name: &str, amount: Option<f64>, date: Date) -> Result<StateChange> {
+		let start = date.to_julian_day();
+		let open = self.get_open_state(name)?;
+		let tx = self.conn.unchecked_transaction()?;
+		let change = match open {
+			Some(open) => {
+				if date < open.start {
+					return Err(anyhow!("{} was opened on {}, so it cannot be closed on {}", name, open.start, date))
+				}
+				tx.execute(self.commands.get("close state").unwrap(), named_params! {":end": start, ":name": name})?;
+				match amount {
+					Some(amount) if open.amount != Some(amount) => {
+						tx.execute(self.commands.get("insert state").unwrap(), named_params! {":name": name, ":amount": amount, ":start": start})?;
+						StateChange::AmountChanged
+					},
+					_ => StateChange::Closed,
+				}
+			},
+			None => {
+				let later = self.conn.query_row(self.commands.get("get later state").unwrap(), named_params! {":name": name, ":date": start}, state_from_row).optional()?;
+				if let Some(State { start: from, end: Some(to), .. }) = later {
+					return Err(anyhow!("{} ran from {} to {}, so it cannot be opened again on {}", name, from, to, date))
+				}
+				tx.execute(self.commands.get("insert state").unwrap(), named_params! {":name": name, ":amount": amount, ":start": start})?;
+				StateChange::Opened
+			},
+		};
+		tx.commit()?;
+
+		Ok(change)
+	}
+
+	pub fn get_states(&self) -> Result<Vec<State>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get states").unwrap())?;
+		let states = stmt.query_map([], state_from_row)?;
+
+		states.collect()
+	}
+
+	pub fn get_open_state(&self, name: &str) -> Result<Option<State>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get open state").unwrap())?;
+		let mut states = stmt.query_map(&[(":name", &name)], state_from_row)?;
+
+		states.next().transpose()
+	}
+
+	/// Returns the states that were in effect on `date`.
+	pub fn get_active_states(&self, date: Date) -> Result<Vec<State>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get active states").unwrap())?;
+		let states = stmt.query_map(&[(":date", &date.to_julian_day())], state_from_row)?;
+
+		states.collect()
+	}
+
+	/// Stores the findings of an analysis run. A finding already on record
+	/// (matched by type, output and input) has its lag and confidence
+	/// refreshed and keeps its id and hidden flag; a new finding is only
+	/// recorded if its confidence reaches `min_confidence`. Findings on
+	/// record that the run did not produce are marked stale. Returns the
+	/// number of findings written.
+	pub fn upsert_recommendations(&self, findings: &[Recommendation], min_confidence: f64) -> Result<usize, Error> {
+		let tx = self.conn.unchecked_transaction()?;
+		let mut written = 0;
+		tx.execute(self.commands.get("mark recommendations stale").unwrap(), [])?;
+		{
+			let mut find = tx.prepare(self.commands.get("find recommendation").unwrap())?;
+			for finding in findings {
+				let kind = finding.kind.as_str();
+				let existing: Option<i64> = find
+					.query_map(named_params! {":type": kind, ":output": finding.output, ":input": finding.input}, |row| row.get(0))?
+					.next()
+					.transpose()?;
+				match existing {
+					Some(id) => {
+						tx.execute(self.commands.get("update recommendation").unwrap(), named_params! {":lag": finding.lag, ":confidence": finding.confidence, ":id": id})?;
+					},
+					None if finding.confidence >= min_confidence => {
+						tx.execute(self.commands.get("insert recommendation").unwrap(), named_params! {
+							":type": kind,
+							":output": finding.output,
+							":input": finding.input,
+							":lag": finding.lag,
+							":confidence": finding.confidence,
+						})?;
+					},
+					None => continue,
+				}
+				written += 1;
+			}
+		}
+		tx.commit()?;
+
+		Ok(written)
+	}
+
+	/// Returns stored findings, most confident first, with stale ones last.
+	pub fn get_recommendations(&self, include_hidden: bool) -> Result<Vec<Recommendation>, Error> {
+		let mut stmt = self.conn.prepare(self.commands.get("get recommendations").unwrap())?;
+		let recommendations = stmt.query_map([], |row| {
+			let kind: String = row.get(1)?;
+			Ok(Recommendation {
+				id: row.get(0)?,
+				kind: RecommendationType::from_str(&kind)
+					.ok_or_else(|| Error::FromSqlConversionFailure(1, Type::Text, format!("unknown recommendation type {}", kind).into()))?,
+				output: row.get(2)?,
+				input: row.get(3)?,
+				lag: row.get(4)?,
+				confidence: row.get(5)?,
+				hidden: row.get(6)?,
+				stale: row.get(7)?,
+			})
+		})?;
+
+		recommendations
+			.filter(|r| include_hidden || !matches!(r, Ok(r) if r.hidden))
+			.collect()
+	}
+
+	/// Hides a finding so it is no longer listed. Returns false if there is
+	/// no finding with that id.
+	pub fn hide_recommendation(&self, id: i64) -> Result<bool, Error> {
+		let changed = self.conn.execute(self.commands.get("hide recommendation").unwrap(), &[(":id", &id)])?;
+		Ok(changed > 0)
+	}
+}
+
+/// Names that entries already use for something other than a field, in
+/// `entries` itself or alongside the fields when exported.
+const RESERVED_NAMES: [&str; 4] = ["id", "date", "tags", "note"];
+
+fn is_reserved(name: &str) -> bool {
+	RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Whether a field could be given this name, which must be usable as a
+/// column of `entries` and not one of the reserved names.
+pub fn valid_field_name(name: &str) -> bool {
+	quote_identifier(name).is_ok() && !is_reserved(name)
+}
+
+/// Quotes a field name for use as a column identifier, rejecting names
+/// that could never be valid columns. Every column name that reaches SQL
+/// must pass through here; values are always bound as parameters instead.
+fn quote_identifier(name: &str) -> Result<String, Error> {
+	if name.trim().is_empty() || name.chars().any(|c| c.is_control()) {
+		return Err(Error::InvalidColumnName(String::from(name)))
+	}
+
+	Ok(format!("\"{}\"", name.replace('"', "\"\"")))
+}
+
+/// The SQL column type and the short code stored in `fields` for a type.
+/// Fields are prompted for in the order of their codes, so the tags field
+/// (`t`) must stay last.
+fn type_codes(data_type: &FieldType) -> (&'static str, &'static str) {
+	match data_type {
+		FieldType::Numeric => ("real", "n"),
+		FieldType::Boolean => ("boolean", "b"),
+		FieldType::Text => ("text", "t"),
+		FieldType::Categorical => ("text", "c"),
+		FieldType::Duration => ("integer", "d"),
+		FieldType::TimeOfDay => ("integer", "h"),
+		FieldType::Count => ("integer", "i"),
+	}
+}
+
+/// Choices are stored one per line, or as NULL for fields without any.
+fn choices_column(meta: &FieldMeta) -> Option<String> {
+	Some(meta.choices.join("\n")).filter(|choices| !choices.is_empty())
+}
+
+/// The columns to select for an entry: its date, then a column for each
+/// field. Tags are kept in their own table rather than as a column.
+fn column_list(fields: &[Field]) -> Result<String, Error> {
+	let mut columns = vec![String::from("date")];
+	for field in fields.iter().filter(|f| f.data_type != FieldType::Text) {
+		columns.push(quote_identifier(&field.name)?);
+	}
+
+	Ok(columns.join(", "))
+}
+
+fn field_from_row(row: &Row) -> Result<Field, Error> {
+	let c1: String = row.get(1)?;
+	let category = match c1.as_str() {
+		"h" => FieldCategory::Hybrid,
+		"o" => FieldCategory::Output,
+		_ => FieldCategory::Input,
+	};
+
+	let c2: String = row.get(2)?;
+	let data_type = match c2.as_str() {
+		"n" => FieldType::Numeric,
+		"b" => FieldType::Boolean,
+		"c" => FieldType::Categorical,
+		"d" => FieldType::Duration,
+		"h" => FieldType::TimeOfDay,
+		"i" => FieldType::Count,
+		_ => FieldType::Text,
+	};
+	let choices: Option<String> = row.get(9)?;
+
+	Ok(Field {
+		name: row.get(0)?,
+		category,
+		data_type,
+		active: row.get(3)?,
+		meta: FieldMeta {
+			min: row.get(4)?,
+			max: row.get(5)?,
+			unit: row.get(6)?,
+			step: row.get(7)?,
+			description: row.get(8)?,
+			choices: choices.map(|c| c.lines().map(String::from).collect()).unwrap_or_default(),
+		},
+	})
+}
+
+/// Picks a file next to `path` to back the database up to before migrating
+/// from `version`, such as `mood.db.v0.bak`.
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+	let file = path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+	let mut backup = path.with_file_name(format!("{}.v{}.bak", file, version));
+	let mut attempt = 1;
+	while backup.exists() {
+		backup = path.with_file_name(format!("{}.v{}.{}.bak", file, version, attempt));
+		attempt += 1;
+	}
+
+	backup
+}
+
+fn state_from_row(row: &Row) -> Result<State, Error> {
+	let parse = |index: usize, day: i32| Date::from_julian_day(day)
+		.map_err(|e| Error::FromSqlConversionFailure(index, Type::Integer, e.into()));
+
+	let start: i32 = row.get(2)?;
+	let end: Option<i32> = row.get(3)?;
+	Ok(State {
+		name: row.get(0)?,
+		amount: row.get(1)?,
+		start: parse(2, start)?,
+		end: end.map(|end| parse(3, end)).transpose()?,
+	})
+}
+
+/// Reads an entry selected with `column_list`, leaving its tags empty.
+fn entry_from_row(row: &Row, fields: &[Field]) -> Result<Entry, Error> {
+	let mut numeric_fields = HashMap::new();
+	let mut boolean_fields = HashMap::new();
+	let mut categorical_fields = HashMap::new();
+
+	let date: i32 = row.get(0)?;
+	for (i, field) in fields.iter().filter(|f| f.data_type != FieldType::Text).enumerate() {
+		match field.data_type {
+			FieldType::Boolean => {
+				let val: Option<bool> = row.get(i + 1)?;
+				if let Some(val) = val {
+					boolean_fields.insert(field.name.clone(), val);
+				}
+			},
+			FieldType::Categorical => {
+				let val: Option<String> = row.get(i + 1)?;
+				if let Some(val) = val {
+					categorical_fields.insert(field.name.clone(), val);
+				}
+			},
+			FieldType::Text => (),
+			_ => {
+				let val: Option<f64> = row.get(i + 1)?;
+				if let Some(val) = val {
+					numeric_fields.insert(field.name.clone(), val);
+				}
+			},
+		}
 	}
 
+	Ok(Entry {
+		date: Date::from_julian_day(date).unwrap(),
+		numeric_fields,
+		boolean_fields,
+		categorical_fields,
+		tags: Vec::new(),
+		note: None,
+	})
 }
 
 #[cfg(test)]
@@ -280,6 +1298,7 @@ pub mod db_tests {
 			category: crate::util::FieldCategory::Output,
 			data_type: crate::util::FieldType::Numeric,
 			active: true,
+			meta: FieldMeta::default(),
 		};
 		
 		let r = dbh.insert_field(&field);
@@ -287,7 +1306,30 @@ pub mod db_tests {
 		assert!(r.is_ok());
 		let vf = dbh.get_fields().unwrap();
 		assert!(vf.len() == 2);
-		assert!(field.eq(vf.get(1).unwrap()));
+		assert!(vf.contains(&field));
+
+		let sleep = Field {
+			name: String::from("sleep"),
+			category: FieldCategory::Input,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta {
+				min: Some(0.0),
+				max: Some(24.0),
+				unit: Some(String::from("hours")),
+				step: Some(0.25),
+				description: Some(String::from("Time asleep, not time in bed")),
+				choices: Vec::new(),
+			},
+		};
+		dbh.insert_field(&sleep).unwrap();
+		assert!(dbh.get_fields().unwrap().contains(&sleep));
+
+		let meta = FieldMeta { min: Some(1.0), max: Some(10.0), ..FieldMeta::default() };
+		assert!(dbh.set_field_meta("mood", &meta).unwrap());
+		assert!(!dbh.set_field_meta("missing", &meta).unwrap());
+		let mood = dbh.get_fields().unwrap().into_iter().find(|f| f.name == "mood").unwrap();
+		assert!(mood.meta == meta);
     }
 
 	#[test]
@@ -298,7 +1340,9 @@ pub mod db_tests {
 			date: Date::from_calendar_date(2022, Month::May, 12).unwrap(),
 			numeric_fields: HashMap::new(),
 			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
 			tags: vec![String::from("argument::doug")],
+			note: None,
 		};
 		let result = dbh.insert_entry(&entry);
 		assert!(result.is_ok());
@@ -309,4 +1353,628 @@ pub mod db_tests {
 		assert!(returned.is_ok());
 		assert!(returned.unwrap().pop().unwrap() == entry);
 	}
+
+	#[test]
+	fn entry_update() {
+		let dbh = setup_db("test_entry_update.db");
+		let _ = dbh.insert_field(&Field {
+			name: String::from("mood"),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		});
+
+		let date = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		assert!(dbh.get_entry(date).unwrap().is_none());
+
+		let mut entry = Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("mood"), 3.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: vec![String::from("ate:gluten")],
+			note: None,
+		};
+		assert!(dbh.insert_entry(&entry).is_ok());
+
+		entry.numeric_fields.insert(String::from("mood"), 6.0);
+		entry.tags.push(String::from("spoke:diego"));
+		let result = dbh.update_entry(&entry);
+		assert!(result.is_ok());
+		assert!(result.unwrap() == 1);
+
+		let returned = dbh.get_entry(date).unwrap();
+		assert!(returned == Some(entry));
+	}
+
+	#[test]
+	fn entry_upsert() {
+		let dbh = setup_db("test_entry_upsert.db");
+		for name in ["mood", "energy"] {
+			let _ = dbh.insert_field(&Field {
+				name: String::from(name),
+				category: FieldCategory::Output,
+				data_type: FieldType::Numeric,
+				active: true,
+				meta: FieldMeta::default(),
+			});
+		}
+
+		let date = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		let first = Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("mood"), 3.0), (String::from("energy"), 4.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: vec![String::from("ate:gluten")],
+			note: None,
+		};
+		let second = Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("mood"), 6.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: vec![String::from("spoke:diego")],
+			note: None,
+		};
+
+		assert!(dbh.upsert_entry(&first, EntryConflict::Abort).unwrap() == EntryWrite::Inserted);
+		assert!(dbh.insert_entry(&second).is_err());
+		assert!(dbh.upsert_entry(&second, EntryConflict::Abort).unwrap() == EntryWrite::Aborted);
+		assert!(dbh.get_entry(date).unwrap() == Some(first.clone()));
+
+		assert!(dbh.upsert_entry(&second, EntryConflict::Merge).unwrap() == EntryWrite::Merged);
+		let merged = dbh.get_entry(date).unwrap().unwrap();
+		assert!(merged.numeric_fields.get("mood") == Some(&6.0));
+		assert!(merged.numeric_fields.get("energy") == Some(&4.0));
+		assert!(merged.tags == vec![String::from("ate:gluten"), String::from("spoke:diego")]);
+
+		assert!(dbh.upsert_entry(&second, EntryConflict::Replace).unwrap() == EntryWrite::Replaced);
+		assert!(dbh.get_entry(date).unwrap() == Some(second));
+		assert!(dbh.get_entries().unwrap().len() == 1);
+	}
+
+	#[test]
+	fn awkward_names() {
+		let dbh = setup_db("test_awkward_names.db");
+		let names = ["o'brien", "humeur_été", "select", "\"quoted\"", "drop table entries;--"];
+		for name in names {
+			let r = dbh.insert_field(&Field {
+				name: String::from(name),
+				category: FieldCategory::Output,
+				data_type: FieldType::Numeric,
+				active: true,
+				meta: FieldMeta::default(),
+			});
+			assert!(r.is_ok(), "failed to insert {}: {:?}", name, r);
+		}
+		assert!(dbh.get_fields().unwrap().len() == names.len() + 1);
+		assert!(dbh.insert_field(&Field {
+			name: String::from(" "),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		}).is_err());
+
+		let date = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		let entry = Entry {
+			date,
+			numeric_fields: names.iter().enumerate().map(|(i, n)| (String::from(*n), i as f64)).collect(),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: vec![String::from("talked:o'brien"), String::from("ate:crème_brûlée"), String::from("select")],
+			note: None,
+		};
+		assert!(dbh.insert_entry(&entry).is_ok());
+		assert!(dbh.get_entry(date).unwrap() == Some(entry.clone()));
+
+		let julian = date.to_julian_day() as u32;
+		assert!(dbh.get_numeric_values("o'brien").unwrap() == vec![(julian, 0.0)]);
+		assert!(dbh.get_numeric_values_between_dates("select", julian, julian).unwrap() == vec![(julian, 2.0)]);
+		assert!(dbh.count_entries("\"quoted\"", julian, julian).unwrap() == 1);
+	}
+
+	#[test]
+	fn sparse_entries() {
+		let dbh = setup_db("test_sparse_entries.db");
+		for (name, data_type) in [("mood", FieldType::Numeric), ("exercise", FieldType::Boolean)] {
+			let _ = dbh.insert_field(&Field {
+				name: String::from(name),
+				category: FieldCategory::Output,
+				data_type,
+				active: true,
+				meta: FieldMeta::default(),
+			});
+		}
+
+		let start = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		for day in 0..10 {
+			let mut entry = Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::new(),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			};
+			if day % 2 == 0 {
+				entry.numeric_fields.insert(String::from("mood"), day as f64);
+			}
+			if day % 3 == 0 {
+				entry.boolean_fields.insert(String::from("exercise"), true);
+			}
+			assert!(dbh.insert_entry(&entry).is_ok());
+		}
+
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries.len() == 10);
+		let second = entries.get(1).unwrap();
+		assert!(second.numeric_fields.is_empty());
+		assert!(second.boolean_fields.is_empty());
+		assert!(second.tags.is_empty());
+
+		let first = start.to_julian_day() as u32;
+		let values = dbh.get_numeric_values("mood").unwrap();
+		assert!(values.len() == 5);
+		assert!(values.iter().all(|(date, val)| (date - first) as f64 == *val));
+		let between = dbh.get_numeric_values_between_dates("mood", first, first + 5).unwrap();
+		assert!(between.len() == 3);
+		assert!(dbh.count_entries("mood", first, first + 5).unwrap() == 3);
+		assert!(dbh.count_entries("exercise", first, first + 9).unwrap() == 4);
+	}
+
+	#[test]
+	fn state_toggling() {
+		let dbh = setup_db("test_state_toggling.db");
+		let day = |d: u8| Date::from_calendar_date(2022, Month::May, d).unwrap();
+
+		assert!(dbh.toggle_state("boston", None, day(1)).unwrap() == StateChange::Opened);
+		assert!(dbh.toggle_state("medication", Some(20.0), day(3)).unwrap() == StateChange::Opened);
+		assert!(dbh.toggle_state("medication", Some(40.0), day(10)).unwrap() == StateChange::AmountChanged);
+		assert!(dbh.toggle_state("medication", None, day(20)).unwrap() == StateChange::Closed);
+		assert!(dbh.toggle_state("boston", None, day(1) - time::Duration::days(1)).is_err());
+
+		let states = dbh.get_states().unwrap();
+		assert!(states.len() == 3);
+		assert!(states[0].is_open());
+		assert!(states[1] == State { name: String::from("medication"), amount: Some(20.0), start: day(3), end: Some(day(10)) });
+		assert!(states[2].amount == Some(40.0) && states[2].duration(day(25)) == 10);
+
+		let active: Vec<State> = dbh.get_active_states(day(10)).unwrap();
+		assert!(active.len() == 2);
+		assert!(active[1].amount == Some(40.0));
+		assert!(dbh.get_active_states(day(20)).unwrap().len() == 1);
+		assert!(dbh.get_active_states(day(1) - time::Duration::days(1)).unwrap().is_empty());
+		assert!(dbh.get_open_state("medication").unwrap().is_none());
+
+		// Reopening is only allowed once the last interval has ended.
+		assert!(dbh.toggle_state("medication", None, day(5)).is_err());
+		assert!(dbh.toggle_state("medication", Some(10.0), day(19)).is_err());
+		assert!(dbh.get_states().unwrap().len() == 3);
+		assert!(dbh.toggle_state("medication", Some(10.0), day(20)).unwrap() == StateChange::Opened);
+	}
+
+	#[test]
+	fn recommendation_storage() {
+		let dbh = setup_db("test_recommendation_storage.db");
+		let finding = |kind, input: &str, lag, confidence| Recommendation {
+			id: 0,
+			kind,
+			output: String::from("mood"),
+			input: String::from(input),
+			lag,
+			confidence,
+			hidden: false,
+			stale: false,
+		};
+
+		let findings = vec![
+			finding(RecommendationType::Tag, "ate:gluten", Some(1), 0.95),
+			finding(RecommendationType::Boolean, "exercise", None, 0.85),
+			finding(RecommendationType::Correlation, "sleep_quality", Some(0), 0.5),
+		];
+		assert!(dbh.upsert_recommendations(&findings, 0.8).unwrap() == 2);
+		let stored = dbh.get_recommendations(false).unwrap();
+		assert!(stored.len() == 2);
+		assert!(stored[0].input == "ate:gluten" && stored[0].lag == Some(1));
+		let gluten_id = stored[0].id;
+
+		assert!(dbh.hide_recommendation(gluten_id).unwrap());
+		assert!(!dbh.hide_recommendation(9999).unwrap());
+		assert!(dbh.get_recommendations(false).unwrap().len() == 1);
+
+		// A rerun keeps the same identity and the hidden flag, even once the
+		// finding weakens at another lag, and marks what it did not find.
+		let rerun = vec![finding(RecommendationType::Tag, "ate:gluten", Some(2), 0.7)];
+		assert!(dbh.upsert_recommendations(&rerun, 0.8).unwrap() == 1);
+		let all = dbh.get_recommendations(true).unwrap();
+		assert!(all.len() == 2);
+		let gluten = all.iter().find(|r| r.id == gluten_id).unwrap();
+		assert!(gluten.hidden && gluten.confidence == 0.7 && gluten.lag == Some(2) && !gluten.stale);
+		assert!(all[1].input == "exercise" && all[1].stale);
+	}
+
+	#[test]
+	fn recommendation_lags() {
+		let path = PathBuf::from("test_recommendation_lags.db");
+		let dbh = setup_db("test_recommendation_lags.db");
+		// Before version 8 the lag was part of the input.
+		dbh.conn.execute_batch("
+			ALTER TABLE recommendations DROP COLUMN lag;
+			ALTER TABLE recommendations DROP COLUMN stale;
+			INSERT INTO recommendations (type, output, input, confidence, hidden) VALUES
+				('correlation', 'mood', 'sleep+0d', 0.6, true),
+				('correlation', 'mood', 'sleep+1d', 0.9, false),
+				('tag', 'mood', 'ate:gluten+2d', 0.8, false),
+				('boolean', 'mood', 'a+b', 0.7, false);
+			PRAGMA user_version = 7;
+		").unwrap();
+		drop(dbh);
+
+		let backup = DbHandler::upgrade(&path).unwrap().unwrap();
+		std::fs::remove_file(&backup).unwrap();
+		let dbh = DbHandler::new(path);
+		let stored = dbh.get_recommendations(true).unwrap();
+		assert!(stored.len() == 3);
+		assert!(stored[0].input == "sleep" && stored[0].lag == Some(1) && stored[0].hidden);
+		assert!(stored[1].input == "ate:gluten" && stored[1].lag == Some(2));
+		assert!(stored[2].input == "a+b" && stored[2].lag.is_none());
+	}
+
+	#[test]
+	fn field_activation() {
+		let dbh = setup_db("test_field_activation.db");
+		for name in ["mood", "caffeine"] {
+			dbh.insert_field(&Field {
+				name: String::from(name),
+				category: FieldCategory::Input,
+				data_type: FieldType::Numeric,
+				active: true,
+				meta: FieldMeta::default(),
+			}).unwrap();
+		}
+		let date = Date::from_calendar_date(2022, Month::May, 1).unwrap();
+		dbh.insert_entry(&Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("mood"), 6.0), (String::from("caffeine"), 2.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		}).unwrap();
+
+		assert!(dbh.set_field_active("caffeine", false).unwrap());
+		assert!(!dbh.set_field_active("missing", false).unwrap());
+		assert!(dbh.get_fields().unwrap().iter().all(|f| f.name != "caffeine"));
+		let all = dbh.get_all_fields().unwrap();
+		assert!(all.iter().any(|f| f.name == "caffeine" && !f.active));
+
+		// Past values stay readable, and survive the entry being replaced.
+		assert!(dbh.get_entries().unwrap()[0].numeric_fields.get("caffeine") == Some(&2.0));
+		let replacement = Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("mood"), 4.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		};
+		dbh.upsert_entry(&replacement, EntryConflict::Replace).unwrap();
+		let stored = dbh.get_entry(date).unwrap().unwrap();
+		assert!(stored.numeric_fields.get("mood") == Some(&4.0));
+		assert!(stored.numeric_fields.get("caffeine") == Some(&2.0));
+
+		assert!(dbh.set_field_active("caffeine", true).unwrap());
+		assert!(dbh.get_fields().unwrap().iter().any(|f| f.name == "caffeine"));
+	}
+
+	#[test]
+	fn field_migration() {
+		let dbh = setup_db("test_field_migration.db");
+		let fields = [("sleep", FieldType::Numeric), ("walk", FieldType::Boolean), ("rating", FieldType::Numeric)];
+		for (name, data_type) in fields {
+			dbh.insert_field(&Field { name: String::from(name), category: FieldCategory::Input, data_type, active: true, meta: FieldMeta::default() }).unwrap();
+		}
+		let start = Date::from_calendar_date(2022, Month::May, 1).unwrap();
+		for day in 0..4 {
+			let mut numeric_fields = HashMap::from([(String::from("rating"), (day % 2) as f64)]);
+			if day != 2 {
+				numeric_fields.insert(String::from("sleep"), 6.0 + day as f64);
+			}
+			dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields,
+				boolean_fields: HashMap::from([(String::from("walk"), day % 2 == 0)]),
+				categorical_fields: HashMap::new(),
+				tags: vec![String::from("ate:pizza")],
+				note: None,
+			}).unwrap();
+		}
+		let recommendation = Recommendation {
+			id: 0,
+			kind: RecommendationType::Correlation,
+			output: String::from("rating"),
+			input: String::from("sleep"),
+			lag: Some(1),
+			confidence: 0.9,
+			hidden: false,
+			stale: false,
+		};
+		dbh.upsert_recommendations(&[recommendation], 0.8).unwrap();
+
+		assert!(dbh.rename_field("sleep", "walk").is_err());
+		assert!(dbh.rename_field("sleep", "DATE").is_err());
+		assert!(dbh.rename_field("tags", "notes").is_err());
+		dbh.rename_field("sleep", "sleep_hours").unwrap();
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries.len() == 4);
+		assert!(entries[0].numeric_fields.get("sleep_hours") == Some(&6.0));
+		assert!(!entries[2].numeric_fields.contains_key("sleep_hours"));
+		assert!(entries[0].tags == vec![String::from("ate:pizza")]);
+		assert!(dbh.get_recommendations(true).unwrap()[0].input == "sleep_hours");
+
+		dbh.convert_field("walk", FieldType::Numeric, false).unwrap();
+		dbh.convert_field("rating", FieldType::Boolean, false).unwrap();
+		assert!(dbh.convert_field("sleep_hours", FieldType::Boolean, false).is_err());
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[0].numeric_fields.get("walk") == Some(&1.0));
+		assert!(entries[1].numeric_fields.get("walk") == Some(&0.0));
+		assert!(entries[1].boolean_fields.get("rating") == Some(&true));
+		assert!(!entries[2].numeric_fields.contains_key("sleep_hours"));
+
+		assert!(dbh.convert_field("sleep_hours", FieldType::Boolean, true).unwrap() == 3);
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[0].boolean_fields.get("sleep_hours") == Some(&true));
+		assert!(!entries[2].boolean_fields.contains_key("sleep_hours"));
+
+		// The rebuilt table still allows only one entry per day.
+		assert!(dbh.insert_entry(&Entry {
+			date: start,
+			numeric_fields: HashMap::new(),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		}).is_err());
+	}
+
+	#[test]
+	fn schema_migration() {
+		// test_v0_fixture.db was made with the original, unversioned schema:
+		// two entries share a date and state dates are text.
+		let path = PathBuf::from("test_schema_migration.db");
+		let backup = PathBuf::from("test_schema_migration.db.v0.bak");
+		let _ = std::fs::remove_file(&backup);
+		std::fs::copy("test_v0_fixture.db", &path).unwrap();
+
+		assert!(DbHandler::upgrade(&path).unwrap() == Some(backup.clone()));
+		assert!(backup.exists());
+		assert!(DbHandler::upgrade(&path).unwrap().is_none());
+
+		let dbh = DbHandler::new(path);
+		assert!(dbh.schema_version().unwrap() == SCHEMA_VERSION);
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries.len() == 3);
+		let merged = &entries[1];
+		assert!(merged.date == Date::from_calendar_date(2022, Month::May, 2).unwrap());
+		assert!(merged.numeric_fields.get("mood") == Some(&6.0));
+		assert!(merged.boolean_fields.get("walk") == Some(&false));
+		assert!(merged.tags == vec![String::from("spoke:diego")]);
+		assert!(entries[0].tags == vec![String::from("ate:pizza")]);
+		assert!(entries[2].tags.is_empty());
+		assert!(dbh.entry_columns().unwrap().iter().all(|(name, _)| name != "tags"));
+		assert!(dbh.insert_entry(&Entry {
+			date: merged.date,
+			numeric_fields: HashMap::new(),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		}).is_err());
+
+		let states = dbh.get_states().unwrap();
+		assert!(states[0].start == Date::from_calendar_date(2022, Month::May, 1).unwrap());
+		assert!(states[0].end == Some(Date::from_calendar_date(2022, Month::May, 3).unwrap()));
+		let active = dbh.get_active_states(Date::from_calendar_date(2022, Month::May, 4).unwrap()).unwrap();
+		assert!(active.len() == 1 && active[0].name == "moved");
+
+		// The backup is the untouched original.
+		let original = DbHandler::connect(&backup).unwrap();
+		assert!(original.schema_version().unwrap() == 0);
+		drop(original);
+		std::fs::remove_file(&backup).unwrap();
+	}
+
+	#[test]
+	fn column_types() {
+		let path = PathBuf::from("test_column_types.db");
+		let dbh = setup_db("test_column_types.db");
+		let field = |name: &str| Field {
+			name: String::from(name),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta::default(),
+		};
+		dbh.insert_field(&field("context")).unwrap();
+		let date = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		dbh.insert_entry(&Entry {
+			date,
+			numeric_fields: HashMap::from([(String::from("context"), 7.5)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		}).unwrap();
+		assert!(dbh.get_entries().unwrap()[0].numeric_fields.get("context") == Some(&7.5));
+		assert!(dbh.entry_columns().unwrap().iter().all(|(_, declared_type)| declared_type.eq_ignore_ascii_case("real")));
+
+		// Before version 7 the column's name was repeated in its declared
+		// type, giving a field named contexts text affinity.
+		dbh.conn.execute_batch("
+			ALTER TABLE entries ADD COLUMN \"contexts\" \"contexts\" real;
+			INSERT INTO fields (name, category, type, active) VALUES ('contexts', 'o', 'n', true);
+			UPDATE entries SET contexts = 2.5;
+			ALTER TABLE recommendations DROP COLUMN lag;
+			ALTER TABLE recommendations DROP COLUMN stale;
+			PRAGMA user_version = 6;
+		").unwrap();
+		assert!(dbh.get_entries().is_err());
+		drop(dbh);
+
+		let backup = DbHandler::upgrade(&path).unwrap().unwrap();
+		std::fs::remove_file(&backup).unwrap();
+		let dbh = DbHandler::new(path);
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[0].numeric_fields.get("contexts") == Some(&2.5));
+		assert!(entries[0].numeric_fields.get("context") == Some(&7.5));
+		assert!(dbh.entry_columns().unwrap().iter().all(|(_, declared_type)| declared_type.eq_ignore_ascii_case("real")));
+	}
+
+	#[test]
+	fn tag_table() {
+		let dbh = setup_db("test_tag_table.db");
+		let start = Date::from_calendar_date(2022, Month::May, 1).unwrap();
+		let days = [
+			vec!["ate:gluten", "spoke:diego"],
+			vec!["talked:diego", "ate:gluten"],
+			vec!["spoke:diego", "talked:diego", "went to the park"],
+		];
+		for (offset, tags) in days.iter().enumerate() {
+			dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(offset as i64),
+				numeric_fields: HashMap::new(),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: tags.iter().map(|t| String::from(*t)).collect(),
+				note: None,
+			}).unwrap();
+		}
+
+		// Tags keep their order, and may contain spaces.
+		let third = dbh.get_entry(start + time::Duration::days(2)).unwrap().unwrap();
+		assert!(third.tags == vec!["spoke:diego", "talked:diego", "went to the park"]);
+		assert!(dbh.get_tags().unwrap()[0] == (String::from("ate:gluten"), 2));
+		assert!(dbh.get_tag_dates("talked:diego").unwrap() == vec![start + time::Duration::days(1), start + time::Duration::days(2)]);
+		assert!(dbh.get_namespaces().unwrap() == vec!["ate", "spoke", "talked"]);
+		assert!(dbh.get_namespace_dates("spoke").unwrap() == vec![start, start + time::Duration::days(2)]);
+		assert!(dbh.get_namespace_dates("ate").unwrap().len() == 2);
+		let recent = dbh.get_recent_tags(start + time::Duration::days(1), 2).unwrap();
+		assert!(recent == vec![(String::from("talked:diego"), 2), (String::from("spoke:diego"), 1)]);
+
+		assert!(dbh.rename_tag("went to the park", "walk:park").unwrap() == TagRename::Renamed);
+		assert!(dbh.rename_tag("talked:diego", "spoke:diego").unwrap() == TagRename::Merged);
+		assert!(dbh.rename_tag("talked:diego", "spoke:diego").is_err());
+		let tags = dbh.get_tags().unwrap();
+		assert!(tags.len() == 3);
+		assert!(tags.contains(&(String::from("spoke:diego"), 3)));
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[2].tags == vec!["spoke:diego", "walk:park"]);
+
+		// Updating an entry replaces its tags.
+		let mut first = entries[0].clone();
+		first.tags = vec![String::from("ate:rice")];
+		dbh.update_entry(&first).unwrap();
+		assert!(dbh.get_entry(start).unwrap().unwrap().tags == vec!["ate:rice"]);
+		assert!(dbh.get_tag_dates("ate:gluten").unwrap().len() == 1);
+	}
+
+	#[test]
+	fn field_types() {
+		let dbh = setup_db("test_field_types.db");
+		let workout = Field {
+			name: String::from("workout"),
+			category: FieldCategory::Input,
+			data_type: FieldType::Categorical,
+			active: true,
+			meta: FieldMeta {
+				choices: vec![String::from("run"), String::from("lift"), String::from("yoga")],
+				..FieldMeta::default()
+			},
+		};
+		dbh.insert_field(&workout).unwrap();
+		assert!(dbh.insert_field(&Field { name: String::from("mood"), category: FieldCategory::Output, ..workout.clone() }).is_err());
+		assert!(dbh.insert_field(&Field { name: String::from("journal"), data_type: FieldType::Text, ..workout.clone() }).is_err());
+		for (name, data_type) in [("bedtime", FieldType::TimeOfDay), ("sleep", FieldType::Duration), ("coffees", FieldType::Count)] {
+			dbh.insert_field(&Field { name: String::from(name), category: FieldCategory::Input, data_type, active: true, meta: FieldMeta::default() }).unwrap();
+		}
+		let fields = dbh.get_fields().unwrap();
+		assert!(fields.contains(&workout));
+		// Tags are still prompted for last.
+		assert!(fields.last().unwrap().data_type == FieldType::Text);
+
+		let start = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		let bedtimes = [1420.0, 20.0, 1430.0, 10.0];
+		for (day, bedtime) in bedtimes.iter().enumerate() {
+			let entry = Entry {
+				date: start + time::Duration::days(day as i64),
+				numeric_fields: HashMap::from([
+					(String::from("bedtime"), *bedtime),
+					(String::from("sleep"), 440.0),
+					(String::from("coffees"), day as f64),
+				]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::from([(String::from("workout"), workout.meta.choices[day % 3].clone())]),
+				tags: Vec::new(),
+				note: None,
+			};
+			dbh.insert_entry(&entry).unwrap();
+		}
+
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[1].numeric_fields.get("bedtime") == Some(&20.0));
+		assert!(entries[1].numeric_fields.get("sleep") == Some(&440.0));
+		assert!(entries[3].numeric_fields.get("coffees") == Some(&3.0));
+		assert!(entries[3].categorical_fields.get("workout") == Some(&String::from("run")));
+
+		// Times either side of midnight are analysed as close together.
+		let bedtimes: Vec<f64> = dbh.get_numeric_values("bedtime").unwrap().iter().map(|(_, time)| time.round()).collect();
+		assert!(bedtimes == vec![-20.0, 20.0, -10.0, 10.0]);
+		let first = start.to_julian_day() as u32;
+		assert!(dbh.get_numeric_values_between_dates("bedtime", first + 2, first + 2).unwrap()[0].1.round() == -10.0);
+	}
+
+	#[test]
+	fn notes() {
+		let dbh = setup_db("test_notes.db");
+		let start = Date::from_calendar_date(2022, Month::May, 12).unwrap();
+		let notes = [Some("Migraine all afternoon.\nSkipped the gym."), None, Some("Long walk; no headache at all")];
+		for (day, note) in notes.iter().enumerate() {
+			let entry = Entry {
+				date: start + time::Duration::days(day as i64),
+				numeric_fields: HashMap::new(),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: note.map(String::from),
+			};
+			dbh.insert_entry(&entry).unwrap();
+		}
+
+		let entries = dbh.get_entries().unwrap();
+		assert!(entries[0].note.as_deref() == Some("Migraine all afternoon.\nSkipped the gym."));
+		assert!(entries[1].note.is_none());
+		assert!(dbh.get_entry(start + time::Duration::days(2)).unwrap().unwrap().note == entries[2].note);
+
+		let found = dbh.search_notes("migraine").unwrap();
+		assert!(found.len() == 1);
+		assert!(found[0].0 == start && found[0].1.contains("[Migraine]"));
+		assert!(dbh.search_notes("head*").unwrap()[0].0 == start + time::Duration::days(2));
+		assert!(dbh.search_notes("\"no headache\"").unwrap().len() == 1);
+		assert!(dbh.search_notes("sunburn").unwrap().is_empty());
+		assert!(dbh.search_notes("\"unclosed").is_err());
+
+		// Blank notes are dropped, and merged notes replace older ones.
+		let mut first = entries[0].clone();
+		first.note = Some(String::from("  "));
+		dbh.update_entry(&first).unwrap();
+		assert!(dbh.get_note(start).unwrap().is_none());
+		assert!(dbh.search_notes("migraine").unwrap().is_empty());
+		let mut update = entries[1].clone();
+		update.note = Some(String::from("Rained"));
+		dbh.upsert_entry(&update, EntryConflict::Merge).unwrap();
+		assert!(dbh.get_note(update.date).unwrap().as_deref() == Some("Rained"));
+	}
 }
\ No newline at end of file
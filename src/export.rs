@@ -0,0 +1,283 @@
+use crate::prelude::*;
+use csv::Writer;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExportFormat {
+	Csv,
+	/// A single document holding the fields, states and entries.
+	Json,
+	/// One entry per line.
+	Jsonl,
+}
+
+impl FromStr for ExportFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"csv" => Ok(ExportFormat::Csv),
+			"json" => Ok(ExportFormat::Json),
+			"jsonl" => Ok(ExportFormat::Jsonl),
+			_ => Err(format!("{} is not a format; expected csv, json or jsonl", s)),
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct ExportField<'a> {
+	name: &'a str,
+	category: String,
+	#[serde(rename = "type")]
+	data_type: String,
+	active: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	unit: &'a Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	min: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	step: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	description: &'a Option<String>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	choices: &'a Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ExportState<'a> {
+	name: &'a str,
+	amount: Option<f64>,
+	start: String,
+	end: Option<String>,
+}
+
+/// An entry with its answers alongside the date, tags and note. Durations
+/// and times of day are given in minutes, as they are stored.
+#[derive(Serialize)]
+struct ExportEntry<'a> {
+	date: String,
+	#[serde(flatten)]
+	values: BTreeMap<&'a str, Value>,
+	tags: &'a Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	note: &'a Option<String>,
+}
+
+impl<'a> ExportEntry<'a> {
+	fn new(entry: &'a Entry, fields: &'a [Field]) -> Self {
+		let mut values = BTreeMap::new();
+		for field in fields {
+			let value = match field.data_type {
+				FieldType::Boolean => entry.boolean_fields.get(&field.name).map(|b| Value::from(*b)),
+				FieldType::Categorical => entry.categorical_fields.get(&field.name).map(|c| Value::from(c.as_str())),
+				FieldType::Text => None,
+				_ => entry.numeric_fields.get(&field.name).map(|v| Value::from(*v)),
+			};
+			if let Some(value) = value {
+				values.insert(field.name.as_str(), value);
+			}
+		}
+
+		ExportEntry {
+			date: entry.date.to_string(),
+			values,
+			tags: &entry.tags,
+			note: &entry.note,
+		}
+	}
+}
+
+/// Checks that no field shares its name with the `date`, `tags` and `note`
+/// columns every export has. Fields can no longer be given those names, but
+/// databases from before they were reserved may still use them.
+pub fn check_field_names(fields: &[Field]) -> Result<()> {
+	match fields.iter().find(|f| !crate::db::valid_field_name(&f.name)) {
+		Some(field) => Err(anyhow!("The field {} has the name of a column every export has; rename it with `mood field rename` first.", field.name)),
+		None => Ok(()),
+	}
+}
+
+/// Writes one row per entry under a `date` column, a column per field and
+/// `tags` and `note` columns. Values are written the way they would be
+/// entered, so durations read as 7h30m. With `prefixed`, field columns are
+/// headed such as `ONmood`, so that `mood import` can recreate the fields.
+/// Only their names, categories and types travel that way: units, ranges,
+/// steps, descriptions, unanswered choices and the order of choices, and
+/// whether a field is deactivated are left behind.
+pub fn write_csv<W: Write>(out: W, fields: &[Field], entries: &[Entry], prefixed: bool) -> Result<()> {
+	let mut writer = Writer::from_writer(out);
+	let mut header = vec![String::from("date")];
+	for field in fields {
+		header.push(if prefixed { field_header(field) } else { field.name.clone() });
+	}
+	header.push(String::from("tags"));
+	header.push(String::from("note"));
+	writer.write_record(&header)?;
+
+	for entry in entries {
+		let mut row = vec![entry.date.to_string()];
+		for field in fields {
+			let cell = match field.data_type {
+				FieldType::Boolean => entry.boolean_fields.get(&field.name).map(bool::to_string),
+				FieldType::Categorical => entry.categorical_fields.get(&field.name).cloned(),
+				FieldType::Text => None,
+				_ => entry.numeric_fields.get(&field.name).map(|v| field.data_type.format_value(*v)),
+			};
+			row.push(cell.unwrap_or_default());
+		}
+		row.push(entry.tags.join(" "));
+		row.push(entry.note.clone().unwrap_or_default());
+		writer.write_record(&row)?;
+	}
+	writer.flush()?;
+
+	Ok(())
+}
+
+/// Writes a single JSON document with the fields, the states and the
+/// entries, each entry holding only the answers it has.
+pub fn write_json<W: Write>(mut out: W, fields: &[Field], states: &[State], entries: &[Entry]) -> Result<()> {
+	#[derive(Serialize)]
+	struct Export<'a> {
+		fields: Vec<ExportField<'a>>,
+		states: Vec<ExportState<'a>>,
+		entries: Vec<ExportEntry<'a>>,
+	}
+
+	let fields_out = fields.iter().map(|f| ExportField {
+		name: &f.name,
+		category: format!("{:?}", f.category).to_lowercase(),
+		data_type: match &f.data_type {
+			FieldType::TimeOfDay => String::from("time_of_day"),
+			data_type => format!("{:?}", data_type).to_lowercase(),
+		},
+		active: f.active,
+		unit: &f.meta.unit,
+		min: f.meta.min,
+		max: f.meta.max,
+		step: f.meta.step,
+		description: &f.meta.description,
+		choices: &f.meta.choices,
+	}).collect();
+	let states = states.iter().map(|s| ExportState {
+		name: &s.name,
+		amount: s.amount,
+		start: s.start.to_string(),
+		end: s.end.map(|d| d.to_string()),
+	}).collect();
+	let entries = entries.iter().map(|e| ExportEntry::new(e, fields)).collect();
+
+	serde_json::to_writer_pretty(&mut out, &Export { fields: fields_out, states, entries })?;
+	writeln!(out)?;
+
+	Ok(())
+}
+
+/// Writes each entry as a JSON object on a line of its own.
+pub fn write_jsonl<W: Write>(mut out: W, fields: &[Field], entries: &[Entry]) -> Result<()> {
+	for entry in entries {
+		serde_json::to_writer(&mut out, &ExportEntry::new(entry, fields))?;
+		writeln!(out)?;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod export_tests {
+	use super::*;
+	use crate::util::test_utils::*;
+	use std::fs;
+
+	#[test]
+	fn round_trip() {
+		let dbh = setup_db("test_export.db");
+		let fields = [
+			("mood", FieldCategory::Output, FieldType::Numeric),
+			("exercise", FieldCategory::Input, FieldType::Boolean),
+			("workout", FieldCategory::Input, FieldType::Categorical),
+			("sleep", FieldCategory::Input, FieldType::Duration),
+			("humeur_été", FieldCategory::Hybrid, FieldType::Numeric),
+			("o'brien, \"visits\"", FieldCategory::Input, FieldType::Count),
+		];
+		for (name, category, data_type) in fields {
+			let mut meta = FieldMeta::default();
+			if data_type == FieldType::Categorical {
+				meta.choices = vec![String::from("yoga"), String::from("run"), String::from("lift")];
+			}
+			dbh.insert_field(&Field { name: String::from(name), category, data_type, active: true, meta }).unwrap();
+		}
+		dbh.set_field_meta("mood", &FieldMeta { min: Some(1.0), max: Some(10.0), description: Some(String::from("1 = awful")), ..FieldMeta::default() }).unwrap();
+		dbh.set_field_active("exercise", false).unwrap();
+		dbh.insert_entry(&Entry {
+			date: parse_date("2022-05-01").unwrap(),
+			numeric_fields: HashMap::from([
+				(String::from("mood"), 7.0),
+				(String::from("sleep"), 450.0),
+				(String::from("humeur_été"), 6.5),
+				(String::from("o'brien, \"visits\""), 2.0),
+			]),
+			boolean_fields: HashMap::from([(String::from("exercise"), true)]),
+			categorical_fields: HashMap::from([(String::from("workout"), String::from("run"))]),
+			tags: vec![String::from("ate:gluten"), String::from("spoke:diego")],
+			note: None,
+		}).unwrap();
+		dbh.insert_entry(&Entry {
+			date: parse_date("2022-05-02").unwrap(),
+			numeric_fields: HashMap::from([(String::from("sleep"), 405.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::from([(String::from("workout"), String::from("lift"))]),
+			tags: Vec::new(),
+			note: Some(String::from("Slept in, then \"a walk\",\nlater.")),
+		}).unwrap();
+		// Fields cannot take the names of the fixed columns.
+		for name in ["note", "Tags", "date"] {
+			let field = Field { name: String::from(name), category: FieldCategory::Input, data_type: FieldType::Numeric, active: true, meta: FieldMeta::default() };
+			assert!(dbh.insert_field(&field).is_err());
+		}
+		let fields: Vec<Field> = dbh.get_all_fields().unwrap().into_iter().filter(|f| f.data_type != FieldType::Text).collect();
+		let entries = dbh.get_entries().unwrap();
+
+		let path = PathBuf::from("test_export.csv");
+		write_csv(fs::File::create(&path).unwrap(), &fields, &entries, true).unwrap();
+		let import = read_csv(&path, &[]).unwrap();
+		assert!(import.errors.is_empty(), "{:?}", import.errors);
+		assert!(import.entries == entries);
+		// Fields come back with their names, categories and types only.
+		assert!(import.fields.iter().map(|f| (&f.name, &f.category, &f.data_type)).eq(fields.iter().map(|f| (&f.name, &f.category, &f.data_type))));
+		assert!(import.fields.iter().all(|f| f.active && f.meta.min.is_none() && f.meta.description.is_none()));
+		let workout = import.fields.iter().find(|f| f.name == "workout").unwrap();
+		assert!(workout.meta.choices == vec!["run", "lift"]);
+		let _ = fs::remove_file(&path);
+
+		let mut out = Vec::new();
+		write_jsonl(&mut out, &fields, &entries).unwrap();
+		let lines: Vec<Value> = String::from_utf8(out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+		assert!(lines.len() == 2);
+		assert!(lines[0]["date"] == "2022-05-01");
+		assert!(lines[0]["exercise"] == true);
+		assert!(lines[0]["humeur_été"] == 6.5);
+		assert!(lines[0]["tags"] == serde_json::json!(["ate:gluten", "spoke:diego"]));
+		assert!(lines[1]["sleep"] == 405.0);
+		assert!(lines[1].get("mood").is_none());
+
+		let mut out = Vec::new();
+		write_json(&mut out, &fields, &[], &entries).unwrap();
+		let document: Value = serde_json::from_slice(&out).unwrap();
+		let mood = document["fields"].as_array().unwrap().iter().find(|f| f["name"] == "mood").unwrap();
+		assert!(mood["type"] == "numeric" && mood["category"] == "output" && mood["max"] == 10.0);
+		assert!(document["entries"].as_array().unwrap().len() == 2);
+
+		// Databases from before the names were reserved may still use them.
+		let mut clashing = fields.clone();
+		clashing[0].name = String::from("Note");
+		assert!(check_field_names(&fields).is_ok());
+		assert!(check_field_names(&clashing).is_err());
+	}
+}
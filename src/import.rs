@@ -0,0 +1,358 @@
+use crate::prelude::*;
+use csv::{Reader, StringRecord};
+use std::collections::hash_map;
+
+/// Entries read from a CSV file, along with the fields its header asks to
+/// create and the rows that could not be read.
+pub struct Import {
+	/// Fields named with a type prefix that do not exist yet.
+	pub fields: Vec<Field>,
+	pub entries: Vec<Entry>,
+	/// Line numbers and what was wrong with them.
+	pub errors: Vec<(u64, String)>,
+}
+
+/// What a column of the file holds.
+enum Column {
+	Date,
+	Tags,
+	Note,
+	/// An answer to a field, which is new if it is not yet in the database.
+	Field(Field, bool),
+}
+
+/// Writes the header a field is exported under, such as `ONmood` for a
+/// numeric output: a category letter (I, O or H), a type letter and the
+/// name. The type letters follow the codes the database uses, so N is
+/// numeric, B boolean, C categorical, D duration, H time of day and I count.
+pub fn field_header(field: &Field) -> String {
+	let category = match field.category {
+		FieldCategory::Input => 'I',
+		FieldCategory::Output => 'O',
+		FieldCategory::Hybrid => 'H',
+	};
+	let data_type = match field.data_type {
+		FieldType::Numeric => 'N',
+		FieldType::Boolean => 'B',
+		FieldType::Categorical => 'C',
+		FieldType::Duration => 'D',
+		FieldType::TimeOfDay => 'H',
+		FieldType::Count => 'I',
+		FieldType::Text => 'T',
+	};
+	format!("{}{}{}", category, data_type, field.name)
+}
+
+/// Reads a header written by `field_header` back into a new field. Tags are
+/// not a field of their own, so the `T` type is not accepted.
+fn parse_field_header(header: &str) -> Option<Field> {
+	let mut chars = header.chars();
+	let category = match chars.next()? {
+		'I' => FieldCategory::Input,
+		'O' => FieldCategory::Output,
+		'H' => FieldCategory::Hybrid,
+		_ => return None,
+	};
+	let data_type = match chars.next()? {
+		'N' => FieldType::Numeric,
+		'B' => FieldType::Boolean,
+		'C' => FieldType::Categorical,
+		'D' => FieldType::Duration,
+		'H' => FieldType::TimeOfDay,
+		'I' => FieldType::Count,
+		_ => return None,
+	};
+	let name = chars.as_str();
+	if !crate::db::valid_field_name(name) {
+		return None
+	}
+
+	Some(Field {
+		name: String::from(name),
+		category,
+		data_type,
+		active: true,
+		meta: FieldMeta::default(),
+	})
+}
+
+/// Works out what each column holds. A header naming an existing field
+/// maps to it as is; otherwise it must carry a type prefix.
+fn read_header(headers: &StringRecord, existing: &[Field]) -> Result<Vec<Column>> {
+	let mut columns = Vec::new();
+	let mut seen: Vec<String> = Vec::new();
+	for header in headers {
+		let header = header.trim().trim_start_matches('\u{feff}');
+		let column = match header.to_lowercase().as_str() {
+			"date" => Column::Date,
+			"tags" => Column::Tags,
+			"note" => Column::Note,
+			_ => match existing.iter().find(|f| f.name == header && f.data_type != FieldType::Text) {
+				Some(field) => Column::Field(field.clone(), false),
+				None => match parse_field_header(header) {
+					Some(field) => match existing.iter().find(|f| f.name == field.name) {
+						Some(known) if known.data_type != field.data_type => {
+							return Err(anyhow!("Column {} would make {} {:?}, but it is already {:?}.", header, field.name, field.data_type, known.data_type))
+						},
+						Some(known) => Column::Field(known.clone(), false),
+						None if field.data_type == FieldType::Categorical && field.category == FieldCategory::Output => {
+							return Err(anyhow!("Column {} would make {} a categorical output, but only numeric outputs are analysed; use IC or HC instead.", header, field.name))
+						},
+						None => Column::Field(field, true),
+					},
+					None => return Err(anyhow!("Column {} is not a known field; new fields need a prefix such as ONmood or IBexercise.", header)),
+				},
+			},
+		};
+		let name = match &column {
+			Column::Date => "date",
+			Column::Tags => "tags",
+			Column::Note => "note",
+			Column::Field(field, _) => &field.name,
+		};
+		if seen.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+			return Err(anyhow!("Column {} appears more than once.", name))
+		}
+		seen.push(name.to_lowercase());
+		columns.push(column);
+	}
+
+	if !columns.iter().any(|c| matches!(c, Column::Date)) {
+		return Err(anyhow!("The file has no date column."))
+	}
+	Ok(columns)
+}
+
+/// Reads a date given as YYYY-MM-DD or as a Julian day number.
+fn read_date(s: &str) -> Result<Date, String> {
+	if let Ok(day) = s.parse::<i32>() {
+		return Date::from_julian_day(day).map_err(|_| format!("{} is not a valid Julian day.", s))
+	}
+	parse_date(s).map_err(|e| format!("{}.", e))
+}
+
+/// Reads one cell into `entry`. New categorical fields gather their choices
+/// from the values they are given, each of which must pass `check_choice`.
+fn read_cell(column: &mut Column, cell: &str, entry: &mut Entry) -> Result<(), String> {
+	match column {
+		Column::Date => (),
+		Column::Tags => {
+			for tag in cell.split_whitespace() {
+				if !entry.tags.iter().any(|t| t == tag) {
+					entry.tags.push(String::from(tag));
+				}
+			}
+		},
+		Column::Note => entry.note = Some(String::from(cell)),
+		Column::Field(field, new) => match field.data_type {
+			FieldType::Text => (),
+			FieldType::Boolean => match parse_boolean(cell) {
+				Some(b) => { entry.boolean_fields.insert(field.name.clone(), b); },
+				None => return Err(format!("{}: expected y or n, not {}.", field.name, cell)),
+			},
+			FieldType::Categorical if *new => {
+				let choice = match field.meta.choices.iter().find(|c| c.eq_ignore_ascii_case(cell)) {
+					Some(choice) => choice.clone(),
+					None => {
+						check_choice(cell).map_err(|message| format!("{}: {}", field.name, message))?;
+						field.meta.choices.push(String::from(cell));
+						String::from(cell)
+					},
+				};
+				entry.categorical_fields.insert(field.name.clone(), choice);
+			},
+			FieldType::Categorical => match field.meta.find_choice(cell) {
+				Some(choice) => { entry.categorical_fields.insert(field.name.clone(), String::from(choice)); },
+				None => return Err(format!("{}: expected one of {}, not {}.", field.name, field.meta.choices.join(", "), cell)),
+			},
+			_ => match field.data_type.parse_value(cell).map(|v| field.meta.validate(v).map(|_| v)) {
+				Some(Ok(v)) => { entry.numeric_fields.insert(field.name.clone(), v); },
+				Some(Err(message)) => return Err(format!("{}: {}", field.name, message)),
+				None => return Err(format!("{}: {} is not a valid {:?} value.", field.name, cell, field.data_type)),
+			},
+		},
+	}
+	Ok(())
+}
+
+/// Reads entries from a CSV file whose columns are `date`, optionally
+/// `tags` and `note`, and one column per field. Blank cells are left
+/// unanswered. Problems with the header, or a new categorical field left
+/// with fewer than two choices, fail the whole read, while problems with a
+/// row are collected so that they can all be reported.
+pub fn read_csv(path: &Path, existing: &[Field]) -> Result<Import> {
+	let mut reader = Reader::from_path(path)?;
+	let mut columns = read_header(reader.headers()?, existing)?;
+	let date_column = columns.iter().position(|c| matches!(c, Column::Date)).unwrap();
+	let mut entries = Vec::new();
+	let mut errors = Vec::new();
+	let mut lines: HashMap<Date, u64> = HashMap::new();
+
+	for record in reader.records() {
+		let record = match record {
+			Ok(record) => record,
+			Err(e) => {
+				let line = e.position().map(|p| p.line()).unwrap_or_default();
+				errors.push((line, e.to_string()));
+				continue
+			},
+		};
+		let line = record.position().map(|p| p.line()).unwrap_or_default();
+		if record.iter().all(|cell| cell.trim().is_empty()) {
+			continue
+		}
+
+		let date = match read_date(record.get(date_column).unwrap_or_default().trim()) {
+			Ok(date) => date,
+			Err(message) => {
+				errors.push((line, message));
+				continue
+			},
+		};
+		match lines.entry(date) {
+			hash_map::Entry::Occupied(first) => {
+				errors.push((line, format!("{} was already given on line {}.", date, first.get())));
+				continue
+			},
+			hash_map::Entry::Vacant(slot) => { slot.insert(line); },
+		}
+
+		let mut entry = Entry {
+			date,
+			numeric_fields: HashMap::new(),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: Vec::new(),
+			note: None,
+		};
+		let mut valid = true;
+		for (column, cell) in columns.iter_mut().zip(record.iter()) {
+			let cell = cell.trim();
+			if cell.is_empty() {
+				continue
+			}
+			if let Err(message) = read_cell(column, cell, &mut entry) {
+				errors.push((line, message));
+				valid = false;
+			}
+		}
+		if valid {
+			entries.push(entry);
+		}
+	}
+
+	let fields: Vec<Field> = columns.into_iter().filter_map(|c| match c {
+		Column::Field(field, true) => Some(field),
+		_ => None,
+	}).collect();
+	for field in fields.iter().filter(|f| f.data_type == FieldType::Categorical) {
+		if let Err(message) = check_choices(&field.meta.choices) {
+			return Err(anyhow!("{} cannot be created from the file: {}", field.name, message))
+		}
+	}
+	entries.sort_by_key(|e| e.date);
+
+	Ok(Import { fields, entries, errors })
+}
+
+#[cfg(test)]
+mod import_tests {
+	use super::*;
+	use crate::util::test_utils::*;
+	use std::fs;
+
+	#[test]
+	fn headers() {
+		let field = parse_field_header("ICworkout").unwrap();
+		assert!(field.category == FieldCategory::Input);
+		assert!(field.data_type == FieldType::Categorical);
+		assert!(field_header(&field) == "ICworkout");
+		assert!(parse_field_header("ONmy mood").unwrap().name == "my mood");
+		assert!(parse_field_header("HBo'brien").unwrap().name == "o'brien");
+		assert!(parse_field_header("ON").is_none());
+		assert!(parse_field_header("ON ").is_none());
+		assert!(parse_field_header("ONmo\nod").is_none());
+		assert!(parse_field_header("XNmood").is_none());
+		assert!(parse_field_header("ONnote").is_none());
+	}
+
+	#[test]
+	fn read_rows() {
+		let dbh = setup_db("test_csv_import.db");
+		let mood = Field {
+			name: String::from("mood"),
+			category: FieldCategory::Output,
+			data_type: FieldType::Numeric,
+			active: true,
+			meta: FieldMeta { min: Some(1.0), max: Some(10.0), ..FieldMeta::default() },
+		};
+		dbh.insert_field(&mood).unwrap();
+		let existing = dbh.get_all_fields().unwrap();
+		let path = PathBuf::from("test_csv_import.csv");
+
+		fs::write(&path, "date,mood,IBexercise,ICworkout,HDsleep,tags,note\n\
+			2022-05-01,7,y,Run,7h30m,ate:gluten spoke:diego,Long day\n\
+			22121,,,,,,\n\
+			2022-05-02,11,n,run,8h,,\n\
+			2022-05-03,6,maybe,lift,6h,,\n\
+			2022-05-01,5,,,,,\n").unwrap();
+		let import = read_csv(&path, &existing).unwrap();
+		assert!(import.fields.len() == 3);
+		let workout = import.fields.iter().find(|f| f.name == "workout").unwrap();
+		assert!(workout.meta.choices == vec!["Run", "lift"]);
+		assert!(import.errors.iter().map(|e| e.0).collect::<Vec<_>>() == vec![4, 5, 6]);
+		assert!(import.entries.len() == 2);
+		let first = import.entries.iter().find(|e| e.date.to_julian_day() == 22121).unwrap();
+		assert!(first.numeric_fields.is_empty() && first.tags.is_empty() && first.note.is_none());
+		let may = import.entries.iter().find(|e| e.note.is_some()).unwrap();
+		assert!(may.numeric_fields.get("sleep") == Some(&450.0));
+		assert!(may.categorical_fields.get("workout").map(String::as_str) == Some("Run"));
+		assert!(may.tags == vec!["ate:gluten", "spoke:diego"]);
+
+		fs::write(&path, "date,energy\n2022-05-01,3\n").unwrap();
+		assert!(read_csv(&path, &existing).is_err());
+		fs::write(&path, "day,ONmood\n2022-05-01,3\n").unwrap();
+		assert!(read_csv(&path, &existing).is_err());
+		fs::write(&path, "date,OBmood\n2022-05-01,y\n").unwrap();
+		assert!(read_csv(&path, &existing).is_err());
+		fs::write(&path, "date,OCworkout\n2022-05-01,run\n2022-05-02,lift\n").unwrap();
+		assert!(read_csv(&path, &existing).is_err());
+		fs::write(&path, "date,ICworkout\n2022-05-01,run\n2022-05-02,run\n").unwrap();
+		assert!(read_csv(&path, &existing).is_err());
+
+		// Numbers would be taken for a choice's position, and choices are
+		// stored one per line.
+		fs::write(&path, "date,ICworkout\n2022-05-01,run\n2022-05-02,1\n2022-05-03,\"lift\nyoga\"\n2022-05-05,lift\n").unwrap();
+		let import = read_csv(&path, &existing).unwrap();
+		assert!(import.errors.iter().map(|e| e.0).collect::<Vec<_>>() == vec![3, 4]);
+		assert!(import.fields[0].meta.choices == vec!["run", "lift"]);
+
+		fs::write(&path, "date,ONmood,IBexercise\n2022-05-01,4,y\n2022-05-02,5,n\n").unwrap();
+		let import = read_csv(&path, &existing).unwrap();
+		assert!(import.errors.is_empty());
+		assert!(import.fields.len() == 1);
+		dbh.insert_entry(&Entry {
+			date: parse_date("2022-05-01").unwrap(),
+			numeric_fields: HashMap::from([(String::from("mood"), 8.0)]),
+			boolean_fields: HashMap::new(),
+			categorical_fields: HashMap::new(),
+			tags: vec![String::from("travel")],
+			note: None,
+		}).unwrap();
+		assert!(dbh.import_entries(&import.fields, &import.entries).unwrap() == (1, 1));
+		let merged = dbh.get_entry(parse_date("2022-05-01").unwrap()).unwrap().unwrap();
+		assert!(merged.numeric_fields.get("mood") == Some(&4.0));
+		assert!(merged.boolean_fields.get("exercise") == Some(&true));
+		assert!(merged.tags == vec!["travel"]);
+
+		fs::write(&path, "date,ONhumeur_été,HBo'brien,ONmy mood\n2022-05-03,6,y,5\n").unwrap();
+		let import = read_csv(&path, &dbh.get_all_fields().unwrap()).unwrap();
+		assert!(import.errors.is_empty() && import.fields.len() == 3);
+		dbh.import_entries(&import.fields, &import.entries).unwrap();
+		let entry = dbh.get_entry(parse_date("2022-05-03").unwrap()).unwrap().unwrap();
+		assert!(entry.numeric_fields.get("humeur_été") == Some(&6.0));
+		assert!(entry.numeric_fields.get("my mood") == Some(&5.0));
+		assert!(entry.boolean_fields.get("o'brien") == Some(&true));
+		let _ = fs::remove_file(&path);
+	}
+}
@@ -1,21 +1,26 @@
 mod db;
 mod util;
 mod analysis;
+mod correlation;
+mod import;
+mod export;
 
 mod prelude {
-	pub use std::path::PathBuf;
+	pub use std::path::{Path, PathBuf};
 	pub use crate::db::DbHandler;
 	pub use crate::util::*;
 	pub use crate::analysis::*;
+	pub use crate::correlation::*;
+	pub use crate::import::*;
+	pub use crate::export::*;
 	pub use std::collections::HashMap;
 	pub use time::Date;
 	pub use anyhow::{Result, anyhow};
-	pub use dirs;
 }
 
 use prelude::*;
 use core::f64;
-use std::{io, fs, os};
+use std::{io, fs};
 // use std::;
 // use trendar;
 use clap::{Parser, Subcommand};
@@ -24,6 +29,9 @@ use clap::{Parser, Subcommand};
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    /// Database to use for this run, overriding both MOOD_DB and the config file
+    #[clap(long, global = true)]
+    db: Option<String>,
     #[clap(subcommand)]
     command: Option<Commands>,
 }
@@ -37,105 +45,323 @@ enum Commands {
 	},
 	#[clap(name = "config")]
 	Configure,
-	Edit,
-	Toggle { tag: Option<String> },
+	/// Revise or backfill the entry for a past day
+	Edit {
+		/// Date of the entry to edit, as YYYY-MM-DD
+		#[clap(short, long)]
+		date: String,
+	},
+	/// Start or stop a long-running state, or list states if none is given
+	Toggle {
+		state: Option<String>,
+		/// Date of the change, as YYYY-MM-DD [default: today]
+		#[clap(short, long)]
+		date: Option<String>,
+		/// Amount associated with the state, such as a medication dose
+		#[clap(short, long)]
+		amount: Option<f64>,
+	},
 	Analyze,
+	/// Correlate numeric inputs with numeric outputs over a range of lags
+	Correlate {
+		/// Largest lag in days to consider [default: the coincidence window]
+		#[clap(short, long)]
+		lags: Option<u32>,
+		/// Write the matrix to this CSV file instead of printing it
+		#[clap(short, long)]
+		output: Option<String>,
+	},
+	/// Manage the fields that are tracked
+	Field {
+		#[clap(subcommand)]
+		action: FieldAction,
+	},
+	/// List tags with the number of days each was recorded on
+	Tags {
+		/// Only list tags in this namespace, such as `ate` for `ate:gluten`
+		#[clap(short, long)]
+		namespace: Option<String>,
+		#[clap(subcommand)]
+		action: Option<TagAction>,
+	},
+	/// List findings saved by `analyze`, most confident first
+	Recommendations {
+		/// Hide the recommendation with this id so it is no longer listed
+		#[clap(long)]
+		hide: Option<i64>,
+		/// Include hidden recommendations
+		#[clap(short, long)]
+		all: bool,
+	},
+	/// Record answers without prompting, such as `mood log mood=7 exercise=y`
+	Log {
+		/// Answers as name=value; booleans take y or n, categorical fields a
+		/// choice, and durations and times of day the same forms as the prompt
+		values: Vec<String>,
+		/// Tags for the day, separated by spaces
+		#[clap(short, long)]
+		tags: Option<String>,
+		/// A note for the day, replacing any existing one
+		#[clap(short, long)]
+		note: Option<String>,
+		/// Date of the entry, as YYYY-MM-DD [default: today]
+		#[clap(short, long)]
+		date: Option<String>,
+	},
+	/// Add entries from a CSV file, merging them into any existing ones
+	Import {
+		/// CSV file with a date column and a column per field; headers such
+		/// as ONmood or IBexercise create fields that do not exist yet
+		file: String,
+		/// Check the file and report what would be imported without writing
+		#[clap(long)]
+		dry_run: bool,
+	},
+	/// Write entries out as CSV, JSON or JSON lines
+	Export {
+		/// One of csv, json (a single document that also holds the fields and
+		/// states) or jsonl (one entry per line)
+		#[clap(short, long, default_value = "csv")]
+		format: ExportFormat,
+		/// First day to export, as YYYY-MM-DD
+		#[clap(long)]
+		from: Option<String>,
+		/// Last day to export, as YYYY-MM-DD
+		#[clap(long)]
+		to: Option<String>,
+		/// Only export these fields, separated by commas
+		#[clap(long)]
+		fields: Option<String>,
+		/// Head CSV columns such as ONmood, so `mood import` can recreate the
+		/// fields
+		#[clap(long)]
+		prefixed: bool,
+		/// Write to this file instead of printing
+		#[clap(short, long)]
+		output: Option<String>,
+	},
+	/// Search the notes of past entries, such as `mood search migraine`
+	Search {
+		/// Words to look for; `head*` matches any word starting with head,
+		/// and quotes match a phrase
+		query: String,
+	},
+}
+
+#[derive(Subcommand)]
+enum FieldAction {
+	/// Show every field, including deactivated ones
+	List,
+	/// Start tracking a new field
+	Add {
+		name: Option<String>,
+	},
+	/// Stop prompting for a field, keeping its past data for analysis
+	Deactivate {
+		name: String,
+	},
+	/// Resume prompting for a deactivated field
+	Reactivate {
+		name: String,
+	},
+	/// Rename a field, keeping its data
+	Rename {
+		old: String,
+		new: String,
+	},
+	/// Set the unit, valid range, step and description of a numeric field,
+	/// or add choices to a categorical one
+	Describe {
+		name: String,
+		#[clap(long)]
+		min: Option<f64>,
+		#[clap(long)]
+		max: Option<f64>,
+		#[clap(long)]
+		unit: Option<String>,
+		#[clap(long)]
+		step: Option<f64>,
+		#[clap(long)]
+		description: Option<String>,
+		/// The full list of choices, separated by commas; existing choices
+		/// must be kept
+		#[clap(long)]
+		choices: Option<String>,
+		/// Forget any details not given alongside this flag
+		#[clap(long)]
+		clear: bool,
+	},
+	/// Change a field between numeric and boolean, converting its data
+	Convert {
+		name: String,
+		/// Type to convert to [(n)umeric/(b)oolean]
+		#[clap(long)]
+		to: String,
+		/// Convert even if values other than 0 and 1 would become true
+		#[clap(long)]
+		force: bool,
+	},
+}
+
+#[derive(Subcommand)]
+enum TagAction {
+	/// Show the days a tag was recorded on
+	Dates {
+		tag: String,
+	},
+	/// Rename a tag, merging it into another if the new name is in use
+	Rename {
+		old: String,
+		new: String,
+	},
 }
 
 fn main() {
     let cli = Cli::parse();
+	let config_path = Config::default_path();
+	let mut config = match Config::load(&config_path) {
+		Ok(config) => config,
+		Err(e) => {
+			println!("Error occurred: {:#}", e);
+			return
+		},
+	};
+
+	let db_source = match db_override(&cli) {
+		Some((db_path, source)) => {
+			if let Some(Commands::Initialize { database }) = &cli.command {
+				// Only `--database` is persisted; the override just picks which
+				// file gets initialized this time.
+				init(database, Some(PathBuf::from(db_path)), config, &config_path);
+				return
+			}
+			config.db_path = db_path;
+			source
+		},
+		None => "config file",
+	};
+
+	let needs_db = !matches!(cli.command, Some(Commands::Initialize { .. }) | Some(Commands::Configure));
+	if needs_db && !config.db_path().exists() {
+		println!("No database found at {} (from the {}). Run `mood init` to create one.", config.db_path, db_source);
+		std::process::exit(1);
+	}
+	if needs_db {
+		match DbHandler::upgrade(&config.db_path()) {
+			Ok(Some(backup)) => println!("Database upgraded to schema version {}; the previous version was saved to {}.", db::SCHEMA_VERSION, backup.display()),
+			Ok(None) => (),
+			Err(e) => {
+				println!("Error occurred: {:#}", e);
+				return
+			},
+		}
+	}
+
 	match &cli.command {
 		Some(command) => {
 			match command {
-    			Commands::Initialize { database } => init(database),
-				Commands::Configure => { println!("Configuring something"); },
-				Commands::Edit => println!("Editing an entry"),
-				Commands::Toggle { tag } => match tag {
-					Some(s) => println!("Toggling the {} state.", s),
-					None => println!("Listing all states in database"),
-    			},
-				Commands::Analyze => analyze_db(),
+    			Commands::Initialize { database } => init(database, None, config, &config_path),
+				Commands::Configure => show_config(&config, &config_path, db_source),
+				Commands::Edit { date } => edit_entry(date, &config),
+				Commands::Toggle { state, date, amount } => toggle_state(&config, state, date, *amount),
+				Commands::Analyze => analyze_db(&config),
+				Commands::Correlate { lags, output } => correlate_db(&config, *lags, output),
+				Commands::Field { action } => manage_fields(&config, action),
+				Commands::Tags { namespace, action } => manage_tags(&config, namespace, action),
+				Commands::Recommendations { hide, all } => recommendations(&config, *hide, *all),
+				Commands::Search { query } => search_notes(&config, query),
+				Commands::Export { format, from, to, fields, prefixed, output } => {
+					if let Err(e) = export_db(&config, *format, from, to, fields, *prefixed, output) {
+						eprintln!("Error occurred: {:#}", e);
+						std::process::exit(1);
+					}
+				},
+				Commands::Import { file, dry_run } => {
+					if let Err(problems) = import_file(&config, file, *dry_run) {
+						for problem in problems {
+							eprintln!("{}", problem);
+						}
+						std::process::exit(1);
+					}
+				},
+				Commands::Log { values, tags, note, date } => {
+					// Scripts rely on the exit status to notice bad input.
+					if let Err(problems) = log_entry(&config, values, tags, note, date) {
+						for problem in problems {
+							eprintln!("{}", problem);
+						}
+						std::process::exit(1);
+					}
+				},
 			}
 		},
-    	None => insert_entry(),
+    	None => insert_entry(&config),
 	}
 }
 
-fn init(database: &Option<String>) {
-	// let config_directory = String::from("C:\\Users\\me\\Documents\\mood");
+/// Returns the database location given on the command line or through the
+/// environment, along with where it came from. `--db` takes precedence over
+/// `MOOD_DB`, and both take precedence over `db_path` in the config file.
+fn db_override(cli: &Cli) -> Option<(String, &'static str)> {
+	if let Some(db) = &cli.db {
+		return Some((db.clone(), "--db flag"))
+	}
+	match std::env::var("MOOD_DB") {
+		Ok(db) if !db.trim().is_empty() => Some((db, "MOOD_DB environment variable")),
+		_ => None,
+	}
+}
+
+fn init(database: &Option<String>, db_override: Option<PathBuf>, mut config: Config, config_path: &Path) {
 	println!("The program will initialize!");
 	if let Some(database) = database {
-		println!("Received database directory: {}", database);
-		let dbh = DbHandler::initialize_db(PathBuf::from(database));
-		match dbh {
-			Ok(_) => println!("Database created successfully!"),
-			Err(e) => println!("Something went wrong! {}", e),
-		}
-	}
-
-	// let mut config_dir = if cfg!(windows) {
-	// 	PathBuf::from("~\\Documents\\mood\\")
-	// } else {
-	// 	PathBuf::from("~/.config/mood/")
-	// };
-	let config_dir = dirs::config_dir();
-	if let Some(mut path) = config_dir {
-		path.push("mood");
-		if !path.exists() {
-			if fs::create_dir_all(&path).is_ok() {
-				path.push("config.toml");
-				create_config(&path);
-			} else {
-				println!("Error occurred when creating config directory.");
-				return
-			}
-		}
-	} else {
-		println!("Error occurred when retrieving user directory.");
+		println!("Received database location: {}", database);
+		config.db_path = database.clone();
+	}
+
+	if let Err(e) = config.save(config_path) {
+		println!("Error occurred when creating config file: {:#}", e);
 		return
 	}
+	println!("Configuration saved to {}.", config_path.display());
 
-	let mut db_dir = dirs::config_dir().unwrap();
-	db_dir.push("mood");
-	db_dir.push("mood.db");
+	let db_path = db_override.unwrap_or_else(|| config.db_path());
+	if let Some(parent) = db_path.parent() {
+		if fs::create_dir_all(parent).is_err() {
+			println!("Error occurred when creating database directory.");
+			return
+		}
+	}
 
-	// TODO: Give option for changing DB location
-	let dbh = if !db_dir.exists() {
-		DbHandler::initialize_db(db_dir).unwrap()
+	let dbh = if !db_path.exists() {
+		match DbHandler::initialize_db(db_path) {
+			Ok(dbh) => {
+				println!("Database created successfully!");
+				dbh
+			},
+			Err(e) => {
+				println!("Something went wrong! {}", e);
+				return
+			},
+		}
 	} else {
-		DbHandler::new(db_dir)
+		DbHandler::new(db_path)
 	};
 	// println!("Config file will be stored at {config_directory}. Please enter where you'd like the database to be stored [{config_directory}]:");
 
 	let mut fields = Vec::new();
 	println!("There are three categories of data that can be tracked; pure inputs, which are considered as causal factors\nto the other categories, pure outputs, which are never considered as inputs to any other data,\nand hybrids, which are treated as both inputs and outputs.\n");
 	loop {
-		let mut field_name = String::new();
-		let mut confirm = String::new();
-		println!("Please enter a new field name (leave empty to finish):");
-		io::stdin().read_line(&mut field_name).expect("Invalid input received");
-		if field_name.trim().is_empty() {
+		let field_name = read_field_name();
+		if field_name.is_empty() {
 			break
 		}
-		field_name = field_name.replace(" ", "_");
-
-		let category = select_category();
-		let field_type = select_type();
-		
-		print!("Name: {field_name}");
-		println!("Category: {:?}", category);
-		println!("Type: {:?}", field_type);
-		println!("Is this data correct? [Y/n]:");
-		io::stdin().read_line(&mut confirm).expect("Invalid input received");
-		if confirm.trim().is_empty() || confirm.trim().to_lowercase() == String::from("y") {
-			fields.push(
-				Field {
-					name: String::from(field_name.trim()),
-					category, 
-					data_type: field_type,
-					active: true,
-				}
-			);
+		if !db::valid_field_name(&field_name) {
+			println!("{} cannot be used as a field name.", field_name);
+			continue
+		}
+		if let Some(field) = prompt_field(&field_name) {
+			fields.push(field);
 		}
 	}
 	println!("Fields added are as below:");
@@ -145,9 +371,322 @@ fn init(database: &Option<String>) {
 	}
 }
 
-fn create_config(_config_directory: &PathBuf) {
-	println!("This is where the config would be created!");
-    // todo!()
+fn read_field_name() -> String {
+	let mut field_name = String::new();
+	println!("Please enter a new field name (leave empty to finish):");
+	io::stdin().read_line(&mut field_name).expect("Invalid input received");
+	field_name.trim().replace(' ', "_")
+}
+
+/// Asks for a new field's category and type, returning the field if the
+/// user confirms it.
+fn prompt_field(field_name: &str) -> Option<Field> {
+	let mut confirm = String::new();
+	let mut category = select_category();
+	let field_type = select_type();
+	while field_type == FieldType::Categorical && category == FieldCategory::Output {
+		println!("Only numeric outputs are analysed, so a categorical field must be an input or a hybrid.");
+		category = select_category();
+	}
+	let meta = match field_type {
+		FieldType::Numeric | FieldType::Count => prompt_field_meta(),
+		FieldType::Categorical => FieldMeta { choices: prompt_choices(), ..FieldMeta::default() },
+		_ => FieldMeta::default(),
+	};
+
+	println!("Name: {}", field_name);
+	println!("Category: {:?}", category);
+	println!("Type: {:?}", field_type);
+	if let Some(label) = meta.label() {
+		println!("Scale: {}", label);
+	}
+	println!("Is this data correct? [Y/n]:");
+	io::stdin().read_line(&mut confirm).expect("Invalid input received");
+	if confirm.trim().is_empty() || confirm.trim().to_lowercase() == "y" {
+		Some(Field {
+			name: String::from(field_name),
+			category,
+			data_type: field_type,
+			active: true,
+			meta,
+		})
+	} else {
+		None
+	}
+}
+
+/// Asks for the optional unit, range, step and description of a numeric
+/// field, until they pass `FieldMeta::check`.
+fn prompt_field_meta() -> FieldMeta {
+	println!("The following details are optional; leave them blank to skip.");
+	let read = |question: &str| {
+		let mut answer = String::new();
+		println!("{}", question);
+		io::stdin().read_line(&mut answer).expect("Invalid input received");
+		Some(String::from(answer.trim())).filter(|a| !a.is_empty())
+	};
+	let read_number = |question: &str| loop {
+		match read(question).map(|a| a.parse::<f64>()) {
+			Some(Ok(number)) => return Some(number),
+			Some(Err(_)) => println!("Data given was not a valid number."),
+			None => return None,
+		}
+	};
+
+	loop {
+		let meta = FieldMeta {
+			unit: read("Unit, such as hours or kcal:"),
+			min: read_number("Lowest valid value:"),
+			max: read_number("Highest valid value:"),
+			step: read_number("Step between valid values, such as 1 or 0.5:"),
+			description: read("Description of the scale, such as \"1 = awful, 10 = great\":"),
+			choices: Vec::new(),
+		};
+		match meta.check() {
+			Ok(()) => return meta,
+			Err(message) => println!("{}", message),
+		}
+	}
+}
+
+/// Asks for the answers a categorical field accepts.
+fn prompt_choices() -> Vec<String> {
+	loop {
+		let mut answer = String::new();
+		println!("Please enter the choices, separated by commas:");
+		io::stdin().read_line(&mut answer).expect("Invalid input received");
+		match parse_choices(&answer) {
+			Ok(choices) => return choices,
+			Err(message) => println!("{}", message),
+		}
+	}
+}
+
+fn manage_fields(config: &Config, action: &FieldAction) {
+	let dbh = DbHandler::new(config.db_path());
+	let fields = match dbh.get_all_fields() {
+		Ok(fields) => fields,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+
+	match action {
+		FieldAction::List => {
+			let width = fields.iter().map(|f| f.name.len()).max().unwrap_or(0).max(4);
+			println!("{:width$}  {:8}  {:11}  {:8}  scale", "name", "category", "type", "status", width = width);
+			for field in &fields {
+				let line = format!(
+					"{:width$}  {:8}  {:11}  {:8}  {}",
+					field.name,
+					format!("{:?}", field.category),
+					format!("{:?}", field.data_type),
+					if field.active { "active" } else { "inactive" },
+					field.meta.label().unwrap_or_default(),
+					width = width,
+				);
+				println!("{}", line.trim_end());
+				if let Some(description) = &field.meta.description {
+					println!("{:width$}  {}", "", description, width = width);
+				}
+			}
+		},
+		FieldAction::Add { name } => {
+			let name = match name {
+				Some(name) => name.trim().replace(' ', "_"),
+				None => read_field_name(),
+			};
+			if name.is_empty() {
+				return
+			}
+			if !db::valid_field_name(&name) {
+				println!("{} cannot be used as a field name.", name);
+				return
+			}
+			if let Some(existing) = fields.iter().find(|f| f.name == name) {
+				if existing.active {
+					println!("A field named {} already exists.", name);
+				} else {
+					println!("A deactivated field named {} already exists; use `mood field reactivate {}` to track it again.", name, name);
+				}
+				return
+			}
+			if let Some(field) = prompt_field(&name) {
+				match dbh.insert_field(&field) {
+					Ok(()) => println!("Field {} added; it will be asked for from the next entry on.", name),
+					Err(e) => println!("Error occurred: {:#}", e),
+				}
+			}
+		},
+		FieldAction::Rename { old, new } => {
+			let new = new.trim().replace(' ', "_");
+			match dbh.rename_field(old, &new) {
+				Ok(()) => println!("Field {} renamed to {}.", old, new),
+				Err(e) => println!("Error occurred: {:#}", e),
+			}
+		},
+		FieldAction::Describe { name, min, max, unit, step, description, choices, clear } => {
+			let field = match fields.iter().find(|f| &f.name == name) {
+				Some(field) => field,
+				None => {
+					println!("There is no field named {}.", name);
+					return
+				},
+			};
+			let scaled = matches!(field.data_type, FieldType::Numeric | FieldType::Count);
+			if !scaled && (min.is_some() || max.is_some() || unit.is_some() || step.is_some()) {
+				println!("Only numeric and count fields have a scale.");
+				return
+			}
+			if field.data_type == FieldType::Text {
+				println!("The {} field cannot be described.", name);
+				return
+			}
+			// Clearing forgets the scale and description, but choices in use by
+			// past entries must stay.
+			let mut meta = if *clear {
+				FieldMeta { choices: field.meta.choices.clone(), ..FieldMeta::default() }
+			} else {
+				field.meta.clone()
+			};
+			if let Some(choices) = choices {
+				if field.data_type != FieldType::Categorical {
+					println!("Only categorical fields have choices.");
+					return
+				}
+				let choices = match parse_choices(choices) {
+					Ok(choices) => choices,
+					Err(message) => {
+						println!("{}", message);
+						return
+					},
+				};
+				if let Some(missing) = meta.choices.iter().find(|old| !choices.contains(old)) {
+					println!("Choices can be added but not removed, and {} is missing.", missing);
+					return
+				}
+				meta.choices = choices;
+			}
+			meta.min = min.or(meta.min);
+			meta.max = max.or(meta.max);
+			meta.unit = unit.clone().or(meta.unit);
+			meta.step = step.or(meta.step);
+			meta.description = description.clone().or(meta.description);
+			if let Err(message) = meta.check() {
+				println!("{}", message);
+				return
+			}
+			match dbh.set_field_meta(name, &meta) {
+				Ok(_) if scaled => println!("Scale of {} set to {}.", name, meta.label().unwrap_or_else(|| String::from("any value"))),
+				Ok(_) => println!("Details of {} updated.", name),
+				Err(e) => println!("Error occurred: {:#}", e),
+			}
+		},
+		FieldAction::Convert { name, to, force } => {
+			let to = match to.to_lowercase().trim() {
+				"n" | "numeric" => FieldType::Numeric,
+				"b" | "boolean" => FieldType::Boolean,
+				_ => {
+					println!("Fields can only be converted to numeric or boolean.");
+					return
+				},
+			};
+			match dbh.convert_field(name, to.clone(), *force) {
+				Ok(0) => println!("Field {} converted to {:?}.", name, to),
+				Ok(collapsed) => println!("Field {} converted to {:?}; {} values other than 0 and 1 became true.", name, to, collapsed),
+				Err(e) => println!("Error occurred: {:#}", e),
+			}
+		},
+		FieldAction::Deactivate { name } | FieldAction::Reactivate { name } => {
+			let active = matches!(action, FieldAction::Reactivate { .. });
+			match fields.iter().find(|f| &f.name == name) {
+				None => println!("There is no field named {}.", name),
+				Some(field) if field.data_type == FieldType::Text => {
+					println!("The {} field cannot be {}.", name, if active { "reactivated" } else { "deactivated" });
+				},
+				Some(field) if field.active == active => {
+					println!("Field {} is already {}.", name, if active { "active" } else { "inactive" });
+				},
+				Some(_) => match dbh.set_field_active(name, active) {
+					Ok(_) if active => println!("Field {} reactivated.", name),
+					Ok(_) => println!("Field {} deactivated; its past data is kept for analysis.", name),
+					Err(e) => println!("Error occurred: {}", e),
+				},
+			}
+		},
+	}
+}
+
+fn manage_tags(config: &Config, namespace: &Option<String>, action: &Option<TagAction>) {
+	let dbh = DbHandler::new(config.db_path());
+	match action {
+		None => list_tags(&dbh, namespace),
+		Some(TagAction::Dates { tag }) => match dbh.get_tag_dates(tag) {
+			Ok(dates) if dates.is_empty() => println!("{} has not been recorded on any day.", tag),
+			Ok(dates) => {
+				for date in dates {
+					println!("{}", date);
+				}
+			},
+			Err(e) => println!("Error occurred: {}", e),
+		},
+		Some(TagAction::Rename { old, new }) => match dbh.rename_tag(old, new) {
+			Ok(TagRename::Renamed) => println!("Tag {} renamed to {}.", old, new),
+			Ok(TagRename::Merged) => println!("Tag {} merged into {}.", old, new),
+			Err(e) => println!("Error occurred: {:#}", e),
+		},
+	}
+}
+
+fn list_tags(dbh: &DbHandler, namespace: &Option<String>) {
+	let namespace = namespace.as_ref().map(|n| n.trim_end_matches(':'));
+	let (tags, namespaces) = match (dbh.get_tags(), dbh.get_namespaces()) {
+		(Ok(tags), Ok(namespaces)) => (tags, namespaces),
+		(Err(e), _) | (_, Err(e)) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+
+	match namespace {
+		Some(namespace) => {
+			let tags: Vec<&(String, u32)> = tags.iter().filter(|(tag, _)| tag_namespaces(tag).contains(&namespace)).collect();
+			if tags.is_empty() {
+				println!("No tags have been recorded in the {} namespace.", namespace);
+				return
+			}
+			for (tag, days) in tags {
+				println!("{:>5}  {}", days, tag);
+			}
+			match dbh.get_namespace_dates(namespace) {
+				Ok(dates) => println!("{:>5}  days with any {}: tag", dates.len(), namespace),
+				Err(e) => println!("Error occurred: {}", e),
+			}
+		},
+		None => {
+			if tags.is_empty() {
+				println!("No tags have been recorded yet.");
+				return
+			}
+			for (tag, days) in &tags {
+				println!("{:>5}  {}", days, tag);
+			}
+			if !namespaces.is_empty() {
+				println!("Namespaces: {}", namespaces.join(", "));
+			}
+		},
+	}
+}
+
+fn show_config(config: &Config, config_path: &Path, db_source: &str) {
+	if !config_path.exists() {
+		println!("No configuration file found at {}; using defaults. Run `mood init` to create one.", config_path.display());
+	} else {
+		println!("Configuration loaded from {}.", config_path.display());
+	}
+	println!("{:#?}", config);
+	println!("Database location taken from the {}.", db_source);
 }
 
 fn select_category() -> FieldCategory {
@@ -172,11 +711,15 @@ fn select_category() -> FieldCategory {
 fn select_type() -> FieldType {
 	let mut field_type = String::new();
 	loop {
-		println!("Please enter the data type [(n)umeric/(b)oolean]:");
+		println!("Please enter the data type [(n)umeric/(b)oolean/(c)ategorical/(d)uration/(t)ime of day/coun(k)]:");
 		io::stdin().read_line(&mut field_type).expect("Invalid input received");
 		let type_option = match field_type.to_lowercase().trim() {
 			"n" | "numeric" => Some(FieldType::Numeric),
 			"b" | "boolean" => Some(FieldType::Boolean),
+			"c" | "categorical" => Some(FieldType::Categorical),
+			"d" | "duration" => Some(FieldType::Duration),
+			"t" | "time" | "time of day" => Some(FieldType::TimeOfDay),
+			"k" | "count" => Some(FieldType::Count),
 			_ => None,
 		};
 		if let Some(ft) = type_option {
@@ -194,98 +737,783 @@ fn select_type() -> FieldType {
 // 	// println!("{:?}", source.sub(dest));
 // }
 
-fn insert_entry() {
-	//TODO: Grab location from config file.
-	let mut db_dir = dirs::config_dir().unwrap();
-	db_dir.push("mood");
-	db_dir.push("mood.db");
+fn insert_entry(config: &Config) {
+	let dbh = DbHandler::new(config.db_path());
+	let fields = match dbh.get_fields() {
+		Ok(fields) => fields,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	let date = time::OffsetDateTime::now_utc().date();
+	let existing = match dbh.get_entry(date) {
+		Ok(existing) => existing,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	let on_conflict = if existing.is_some() {
+		println!("An entry already exists for today, {}.", date);
+		select_conflict()
+	} else {
+		EntryConflict::Abort
+	};
+	if on_conflict == EntryConflict::Abort && existing.is_some() {
+		println!("Leaving the existing entry untouched. Use `mood edit` to revise it.");
+		return
+	}
 
-	let dbh = DbHandler::new(db_dir);
-	let fields = dbh.get_fields().unwrap();
-	let mut num_hm = HashMap::new();
-	let mut bool_hm = HashMap::new();
-	let mut tags = Vec::new();
-	let date = Date::from(time::OffsetDateTime::now_utc().date());
 	println!("Entering data for today, {}.", date);
 	println!("If you do not wish to enter data, leave the field blank.");
+	let entry = prompt_entry(&fields, date, None, &TagHints::load(&dbh, date));
+
+	match dbh.upsert_entry(&entry, on_conflict) {
+		Ok(EntryWrite::Inserted) => println!("Entry added to database."),
+		Ok(EntryWrite::Merged) => println!("Entry merged into the existing one for {}.", date),
+		Ok(EntryWrite::Replaced) => println!("Existing entry for {} replaced.", date),
+		Ok(EntryWrite::Aborted) => println!("Leaving the existing entry untouched."),
+		Err(e) => println!("Error occurred: {}", e),
+	}
+}
+
+/// Merges answers given on the command line into the entry for `date`,
+/// returning every problem found if any answer is unusable.
+fn log_entry(config: &Config, values: &[String], tags: &Option<String>, note: &Option<String>, date: &Option<String>) -> Result<(), Vec<String>> {
+	if values.is_empty() && tags.is_none() && note.is_none() {
+		return Err(vec![String::from("Nothing to log; give answers as name=value, or use --tags or --note.")])
+	}
+	let date = match date.as_deref().map(parse_date) {
+		Some(Ok(date)) => date,
+		Some(Err(e)) => return Err(vec![e.to_string()]),
+		None => time::OffsetDateTime::now_utc().date(),
+	};
+
+	let dbh = DbHandler::new(config.db_path());
+	let fields = dbh.get_fields().map_err(|e| vec![format!("Error occurred: {}", e)])?;
+	let mut entry = parse_log_values(&fields, date, values)?;
+	for tag in tags.iter().flat_map(|t| t.split_whitespace()) {
+		if !entry.tags.iter().any(|t| t == tag) {
+			entry.tags.push(String::from(tag));
+		}
+	}
+	entry.note = note.clone();
+
+	match dbh.upsert_entry(&entry, EntryConflict::Merge) {
+		Ok(EntryWrite::Inserted) => println!("Entry for {} added to database.", date),
+		Ok(_) => println!("Entry merged into the existing one for {}.", date),
+		Err(e) => return Err(vec![format!("Error occurred: {}", e)]),
+	}
+
+	Ok(())
+}
+
+/// Reads entries from a CSV file and writes them all in one go, or only
+/// summarises them for a dry run. Nothing is written if any row is unusable.
+fn import_file(config: &Config, file: &str, dry_run: bool) -> Result<(), Vec<String>> {
+	let dbh = DbHandler::new(config.db_path());
+	let fields = dbh.get_all_fields().map_err(|e| vec![format!("Error occurred: {}", e)])?;
+	let import = read_csv(Path::new(file), &fields).map_err(|e| vec![format!("Could not import {}: {}", file, e)])?;
+	if !import.errors.is_empty() {
+		let mut problems: Vec<String> = import.errors.iter().map(|(line, message)| format!("Line {}: {}", line, message)).collect();
+		problems.push(String::from("Nothing was imported."));
+		return Err(problems)
+	}
+
+	let (first, last) = match (import.entries.first(), import.entries.last()) {
+		(Some(first), Some(last)) => (first.date, last.date),
+		_ => {
+			println!("{} holds no entries.", file);
+			return Ok(())
+		},
+	};
+	println!("{} entries from {} to {}.", import.entries.len(), first, last);
+	for field in &import.fields {
+		match field.meta.label() {
+			Some(label) => println!("New field: {} ({:?} {:?}, {})", field.name, field.category, field.data_type, label),
+			None => println!("New field: {} ({:?} {:?})", field.name, field.category, field.data_type),
+		}
+	}
+
+	if dry_run {
+		let mut existing = 0;
+		for entry in &import.entries {
+			match dbh.get_entry(entry.date) {
+				Ok(Some(_)) => existing += 1,
+				Ok(None) => (),
+				Err(e) => return Err(vec![format!("Error occurred: {}", e)]),
+			}
+		}
+		println!("{} would be merged into existing entries.", existing);
+		println!("Dry run; nothing was written.");
+		return Ok(())
+	}
+
+	match dbh.import_entries(&import.fields, &import.entries) {
+		Ok((inserted, merged)) => println!("Imported {} new entries and merged {} into existing ones.", inserted, merged),
+		Err(e) => return Err(vec![format!("Error occurred: {:#}", e), String::from("Nothing was imported.")]),
+	}
+
+	Ok(())
+}
+
+/// Writes the entries between `from` and `to` in the chosen format, to
+/// `output` or to the terminal.
+// `is_none_or` would need Rust 1.82.
+#[allow(clippy::unnecessary_map_or)]
+fn export_db(config: &Config, format: ExportFormat, from: &Option<String>, to: &Option<String>, names: &Option<String>, prefixed: bool, output: &Option<String>) -> Result<()> {
+	let from = from.as_deref().map(parse_date).transpose()?;
+	let to = to.as_deref().map(parse_date).transpose()?;
+	if prefixed && format != ExportFormat::Csv {
+		return Err(anyhow!("--prefixed only applies to CSV exports."))
+	}
+
+	let dbh = DbHandler::new(config.db_path());
+	let mut fields: Vec<Field> = dbh.get_all_fields()?.into_iter().filter(|f| f.data_type != FieldType::Text).collect();
+	if let Some(names) = names {
+		let names: Vec<&str> = names.split(',').map(str::trim).filter(|n| !n.is_empty()).collect();
+		if let Some(unknown) = names.iter().find(|n| !fields.iter().any(|f| f.name == **n)) {
+			return Err(anyhow!("There is no field named {}.", unknown))
+		}
+		fields.retain(|f| names.contains(&f.name.as_str()));
+	}
+	check_field_names(&fields)?;
+	let in_range = |date: Date| from.map_or(true, |from| date >= from) && to.map_or(true, |to| date <= to);
+	let entries: Vec<Entry> = dbh.get_entries()?.into_iter().filter(|e| in_range(e.date)).collect();
+
+	let out: Box<dyn io::Write> = match output {
+		Some(path) => Box::new(fs::File::create(path).map_err(|e| anyhow!("Could not create {}: {}", path, e))?),
+		None => Box::new(io::stdout().lock()),
+	};
+	match format {
+		ExportFormat::Csv => write_csv(out, &fields, &entries, prefixed)?,
+		ExportFormat::Jsonl => write_jsonl(out, &fields, &entries)?,
+		ExportFormat::Json => {
+			// States overlapping the range, including ones still ongoing.
+			let states: Vec<State> = dbh.get_states()?.into_iter()
+				.filter(|s| to.map_or(true, |to| s.start <= to) && from.map_or(true, |from| s.end.map_or(true, |end| end >= from)))
+				.collect();
+			write_json(out, &fields, &states, &entries)?
+		},
+	}
+	if let Some(path) = output {
+		println!("Exported {} entries to {}.", entries.len(), path);
+	}
+
+	Ok(())
+}
+
+/// Builds an entry for `date` from `name=value` answers, checking each
+/// against the active fields. Every problem is reported, not just the first.
+fn parse_log_values(fields: &[Field], date: Date, values: &[String]) -> Result<Entry, Vec<String>> {
+	let mut entry = Entry {
+		date,
+		numeric_fields: HashMap::new(),
+		boolean_fields: HashMap::new(),
+		categorical_fields: HashMap::new(),
+		tags: Vec::new(),
+		note: None,
+	};
+	let mut answered = Vec::new();
+	let mut problems = Vec::new();
+
+	for value in values {
+		let (name, answer) = match value.split_once('=') {
+			Some((name, answer)) => (name.trim(), answer.trim()),
+			None => {
+				problems.push(format!("{} is not of the form name=value.", value));
+				continue
+			},
+		};
+		let field = match fields.iter().find(|f| f.name == name) {
+			Some(field) => field,
+			None => {
+				problems.push(format!("There is no active field named {}.", name));
+				continue
+			},
+		};
+		if answered.contains(&name) {
+			problems.push(format!("{} is given more than once.", name));
+			continue
+		}
+		answered.push(name);
+
+		match field.data_type {
+			FieldType::Text => problems.push(String::from("Tags are given with --tags.")),
+			FieldType::Boolean => match parse_boolean(answer) {
+				Some(b) => { entry.boolean_fields.insert(field.name.clone(), b); },
+				None => problems.push(format!("{}: expected y or n, not {}.", name, answer)),
+			},
+			FieldType::Categorical => match field.meta.find_choice(answer) {
+				Some(choice) => { entry.categorical_fields.insert(field.name.clone(), String::from(choice)); },
+				None => problems.push(format!("{}: expected one of {}, not {}.", name, field.meta.choices.join(", "), answer)),
+			},
+			_ => match field.data_type.parse_value(answer).map(|v| field.meta.validate(v).map(|_| v)) {
+				Some(Ok(v)) => { entry.numeric_fields.insert(field.name.clone(), v); },
+				Some(Err(message)) => problems.push(format!("{}: {}", name, message)),
+				None => problems.push(format!("{}: {}", name, invalid_answer(&field.data_type))),
+			},
+		}
+	}
+
+	if problems.is_empty() {
+		Ok(entry)
+	} else {
+		Err(problems)
+	}
+}
+
+fn select_conflict() -> EntryConflict {
+	loop {
+		let mut choice = String::new();
+		println!("Would you like to (m)erge new answers into it, (r)eplace it, or (a)bort? [m/r/A]:");
+		io::stdin().read_line(&mut choice).expect("Invalid input received");
+		match choice.to_lowercase().trim() {
+			"m" | "merge" => return EntryConflict::Merge,
+			"r" | "replace" => return EntryConflict::Replace,
+			"" | "a" | "abort" => return EntryConflict::Abort,
+			_ => println!("Invalid choice. Please select one of the listed options."),
+		}
+	}
+}
+
+fn edit_entry(date: &str, config: &Config) {
+	let date = match parse_date(date) {
+		Ok(date) => date,
+		Err(e) => {
+			println!("{}", e);
+			return
+		},
+	};
+
+	let dbh = DbHandler::new(config.db_path());
+	let fields = match dbh.get_fields() {
+		Ok(fields) => fields,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	let existing = match dbh.get_entry(date) {
+		Ok(existing) => existing,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+
+	match existing {
+		Some(_) => println!("Editing the entry for {}.", date),
+		None => println!("No entry exists for {}; creating a new one.", date),
+	}
+	println!("Current values are shown in brackets; leave the field blank to keep them, or enter {} to clear them.", CLEAR);
+	let entry = prompt_entry(&fields, date, existing.as_ref(), &TagHints::load(&dbh, date));
+
+	// Every current value was offered, so replacing the entry drops only
+	// those that were cleared.
+	match dbh.upsert_entry(&entry, EntryConflict::Replace) {
+		Ok(_) => println!("Entry for {} saved to database.", date),
+		Err(e) => println!("Error occurred: {}", e),
+	}
+}
+
+/// The answer that clears a field's current value, or every tag.
+const CLEAR: &str = "-";
+
+/// Prompts for every field in `fields`, offering the values in `current`
+/// (if any) as defaults. Tags are checked against those already recorded.
+fn prompt_entry(fields: &[Field], date: Date, current: Option<&Entry>, hints: &TagHints) -> Entry {
+	let mut num_hm = HashMap::new();
+	let mut bool_hm = HashMap::new();
+	let mut choice_hm = HashMap::new();
+	let mut tags = current.map(|e| e.tags.clone()).unwrap_or_default();
 	for field in fields {
-		// println!("Back in the loop");
 		match field.data_type {
-			FieldType::Numeric => {
-				let data = get_numeric_data(&field.name);
-				if let Some(data) = data {
-					num_hm.insert(field.name, data);
+			FieldType::Numeric | FieldType::Duration | FieldType::TimeOfDay | FieldType::Count => {
+				let default = current.and_then(|e| e.numeric_fields.get(&field.name).copied());
+				if let Some(data) = get_numeric_data(field, default) {
+					num_hm.insert(field.name.clone(), data);
+				}
+			},
+			FieldType::Categorical => {
+				let default = current.and_then(|e| e.categorical_fields.get(&field.name).cloned());
+				if let Some(data) = get_categorical_data(field, default) {
+					choice_hm.insert(field.name.clone(), data);
 				}
 			},
 			FieldType::Boolean => {
-				let data = get_boolean_data(&field.name);
-				if let Some(data) = data {
-					bool_hm.insert(field.name, data);
+				let default = current.and_then(|e| e.boolean_fields.get(&field.name).copied());
+				if let Some(data) = get_boolean_data(&field.name, default) {
+					bool_hm.insert(field.name.clone(), data);
 				}
 			},
-			FieldType::Text => {
+			FieldType::Text => loop {
 				let mut data = String::new();
 				println!("Please write down any notable tags for the day, separated by spaces.");
+				if !hints.recent.is_empty() {
+					let picks: Vec<String> = hints.recent.iter().enumerate().map(|(i, tag)| format!("{}) {}", i + 1, tag)).collect();
+					println!("Recent tags: {}", picks.join("  "));
+					println!("Enter a number to use a recent tag, or end a tag with * to complete it.");
+				}
+				if current.is_some() {
+					println!("[{}]", tags.join(" "));
+				}
 				io::stdin().read_line(&mut data).expect("Invalid input received");
-				tags = data
-					.split_ascii_whitespace()
-					.filter(|s| s.len() > 0)
-					.map(|s| String::from(s))
-					.collect();
-				// println!("{:?}", tags);
+				if data.trim().is_empty() && current.is_some() {
+					break
+				}
+				if data.trim() == CLEAR {
+					tags.clear();
+					break
+				}
+				if let Some(resolved) = resolve_tags(&data, hints) {
+					tags = resolved;
+					break
+				}
+				println!("Please enter the tags again.");
 			},
 		}
 	}
 
-	let entry = Entry {
+	Entry {
 		date,
 		numeric_fields: num_hm,
 		boolean_fields: bool_hm,
+		categorical_fields: choice_hm,
 		tags,
+		note: prompt_note(date, current.and_then(|e| e.note.clone())),
+	}
+}
+
+/// Offers to write the day's note, or edit the current one, in the user's
+/// editor. Returns the note to keep, which is `None` if it was left blank.
+fn prompt_note(date: Date, current: Option<String>) -> Option<String> {
+	let mut answer = String::new();
+	match &current {
+		Some(note) => {
+			println!("Note: {}", note.lines().next().unwrap_or_default());
+			print!("Would you like to edit the note for the day? [y/N]: ");
+		},
+		None => print!("Would you like to write a note for the day? [y/N]: "),
+	}
+	io::Write::flush(&mut io::stdout()).expect("flush failed!");
+	io::stdin().read_line(&mut answer).expect("Invalid input received");
+	if answer.trim().to_lowercase() != "y" {
+		return current
+	}
+
+	let note = match edit_note(date, current.as_deref().unwrap_or_default()) {
+		Ok(note) => note,
+		Err(e) => {
+			println!("Could not open an editor ({:#}); write the note on a single line instead:", e);
+			let mut line = String::new();
+			io::stdin().read_line(&mut line).expect("Invalid input received");
+			line
+		},
 	};
+	Some(String::from(note.trim())).filter(|n| !n.is_empty())
+}
 
-	match dbh.insert_entry(&entry) {
-		Ok(_) => println!("Entry added to database."),
-		Err(e) => println!("Error occurred: {}", e),
+/// Opens `$VISUAL` or `$EDITOR` (or `vi`, failing both) on a temporary file
+/// holding `current`, returning what was saved. If the editor fails, the
+/// file is kept so that nothing typed is lost.
+fn edit_note(date: Date, current: &str) -> Result<String> {
+	let editor = std::env::var("VISUAL")
+		.or_else(|_| std::env::var("EDITOR"))
+		.unwrap_or_else(|_| String::from("vi"));
+	// Editors are often set with arguments, such as `code --wait`.
+	let mut words = editor.split_whitespace();
+	let program = words.next().ok_or_else(|| anyhow!("the editor setting is empty"))?;
+	let (path, mut file) = create_note_file(date)?;
+	// Saved notes are trimmed, so give editors back their final newline.
+	let contents = if current.is_empty() { String::new() } else { format!("{}\n", current) };
+	io::Write::write_all(&mut file, contents.as_bytes())?;
+	drop(file);
+
+	match std::process::Command::new(program).args(words).arg(&path).status() {
+		Ok(status) if status.success() => {
+			let note = fs::read_to_string(&path)?;
+			let _ = fs::remove_file(&path);
+			Ok(note)
+		},
+		Ok(status) => Err(anyhow!("{} exited with {}; the note is kept in {}", program, status, path.display())),
+		Err(e) => {
+			let _ = fs::remove_file(&path);
+			Err(anyhow!("{}: {}", program, e))
+		},
 	}
 }
 
-fn get_boolean_data(name: &str) -> Option<bool> {
+/// Creates a file for editing a note in the temporary directory. Its name
+/// has a random part and it must not already exist, so neither another
+/// session's note nor a link left in its place is written through.
+fn create_note_file(date: Date) -> io::Result<(PathBuf, fs::File)> {
+	use std::hash::{BuildHasher, Hasher};
+
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	loop {
+		let mut suffix = std::collections::hash_map::RandomState::new().build_hasher();
+		suffix.write_u32(std::process::id());
+		let path = std::env::temp_dir().join(format!("mood-note-{}-{:016x}.txt", date, suffix.finish()));
+		match options.open(&path) {
+			Ok(file) => return Ok((path, file)),
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Tags already in the database, used to help fill in the tags prompt.
+#[derive(Default)]
+struct TagHints {
+	/// Every tag, most used first.
+	known: Vec<String>,
+	namespaces: Vec<String>,
+	/// The most used tags of the last few weeks, offered as quick picks.
+	recent: Vec<String>,
+}
+
+/// Days of history that quick picks are drawn from.
+const RECENT_TAG_DAYS: i64 = 28;
+const QUICK_PICKS: u32 = 5;
+
+impl TagHints {
+	fn load(dbh: &DbHandler, date: Date) -> Self {
+		let since = date - time::Duration::days(RECENT_TAG_DAYS);
+		match (dbh.get_tags(), dbh.get_namespaces(), dbh.get_recent_tags(since, QUICK_PICKS)) {
+			(Ok(known), Ok(namespaces), Ok(recent)) => TagHints {
+				known: known.into_iter().map(|(tag, _)| tag).collect(),
+				namespaces,
+				recent: recent.into_iter().map(|(tag, _)| tag).collect(),
+			},
+			_ => TagHints::default(),
+		}
+	}
+}
+
+/// Turns the tags line into tags, expanding quick pick numbers and
+/// completions and checking new tags for typos. Returns `None` if the line
+/// should be entered again.
+fn resolve_tags(line: &str, hints: &TagHints) -> Option<Vec<String>> {
+	let mut tags: Vec<String> = Vec::new();
+	for word in line.split_ascii_whitespace() {
+		let tag = if let Some(pick) = word.parse::<usize>().ok().and_then(|n| hints.recent.get(n.wrapping_sub(1))) {
+			pick.clone()
+		} else if let Some(prefix) = word.strip_suffix('*') {
+			match complete_tag(prefix, &hints.known).as_slice() {
+				[] => {
+					println!("No tag starts with {}.", prefix);
+					return None
+				},
+				[tag] => {
+					println!("Completed {} to {}.", word, tag);
+					tag.to_string()
+				},
+				matches => {
+					println!("{} could be any of: {}", word, matches.join(", "));
+					return None
+				},
+			}
+		} else {
+			confirm_new_tag(word, hints)?
+		};
+
+		if !tags.contains(&tag) {
+			tags.push(tag);
+		}
+	}
+
+	Some(tags)
+}
+
+/// Checks a tag that may not have been used before: one that looks like a
+/// typo of a known tag can be swapped for it, and one that starts a new
+/// namespace has to be confirmed. Returns `None` if the tag was rejected.
+fn confirm_new_tag(tag: &str, hints: &TagHints) -> Option<String> {
+	if let Some(close) = closest_tag(tag, &hints.known) {
+		let mut choice = String::new();
+		println!("{} has not been used before, but {} has. Use {} instead? [Y/n]:", tag, close, close);
+		io::stdin().read_line(&mut choice).expect("Invalid input received");
+		if choice.trim().is_empty() || choice.trim().to_lowercase() == "y" {
+			return Some(String::from(close))
+		}
+	}
+
+	if hints.namespaces.is_empty() {
+		return Some(String::from(tag))
+	}
+	if let Some(namespace) = unknown_namespace(tag, &hints.namespaces) {
+		let mut confirm = String::new();
+		println!("{} starts a new namespace, {}; the namespaces in use are {}.", tag, namespace, hints.namespaces.join(", "));
+		println!("Keep it? [y/N]:");
+		io::stdin().read_line(&mut confirm).expect("Invalid input received");
+		if confirm.trim().to_lowercase() != "y" {
+			return None
+		}
+	}
+
+	Some(String::from(tag))
+}
+
+fn get_boolean_data(name: &str, default: Option<bool>) -> Option<bool> {
 	let mut data = String::new();
+	let choices = match default {
+		Some(true) => "Y/n",
+		Some(false) => "y/N",
+		None => "y/n",
+	};
+	print!("Did {} occur [{}]: ", name, choices);
+	io::Write::flush(&mut io::stdout()).expect("flush failed!");
+	io::stdin().read_line(&mut data).expect("Invalid input received");
+	match data.to_ascii_lowercase().trim() {
+		"y" => Some(true),
+		"n" => Some(false),
+		CLEAR => None,
+		_ => default,
+	}
+}
+
+fn get_numeric_data(field: &Field, default: Option<f64>) -> Option<f64> {
+	let name = match field.meta.label() {
+		Some(label) => format!("{} ({})", field.name, label),
+		None => field.name.clone(),
+	};
+	if let Some(description) = &field.meta.description {
+		println!("{}: {}", field.name, description);
+	}
 	loop {
-		print!("Did {} occur today [y/n]: ", name);
+		let mut data = String::new();
+		match default {
+			Some(val) => print!("How would you report the value of {} [{}]: ", name, field.data_type.format_value(val)),
+			None => print!("How would you report the value of {}: ", name),
+		}
 		io::Write::flush(&mut io::stdout()).expect("flush failed!");
 		io::stdin().read_line(&mut data).expect("Invalid input received");
-		match data.to_ascii_lowercase().trim() {
-			"y" => return Some(true),
-			"n" => return Some(false),
-			_ => return None,
+		if data.trim().is_empty() {
+			return default;
+		}
+		if data.trim() == CLEAR {
+			return None;
+		}
+		match field.data_type.parse_value(&data) {
+			Some(num) => match field.meta.validate(num) {
+				Ok(()) => return Some(num),
+				Err(message) => println!("\n{}", message),
+			},
+			None => println!("\n{}", invalid_answer(&field.data_type)),
 		}
 	}
 }
 
-fn get_numeric_data(name: &str) -> Option<f64> {
-	let mut data = String::new();
+/// Explains what an answer to a numeric field of this type should look like.
+fn invalid_answer(data_type: &FieldType) -> &'static str {
+	match data_type {
+		FieldType::Duration => "Data given was not a valid duration, such as 7h20m, 45m or 1:30.",
+		FieldType::TimeOfDay => "Data given was not a valid time of day, such as 23:40 or 7:05am.",
+		FieldType::Count => "Data given was not a valid count, such as 0 or 3.",
+		_ => "Data given was not a valid number.",
+	}
+}
+
+fn get_categorical_data(field: &Field, default: Option<String>) -> Option<String> {
+	if let Some(description) = &field.meta.description {
+		println!("{}: {}", field.name, description);
+	}
+	let picks: Vec<String> = field.meta.choices.iter().enumerate().map(|(i, choice)| format!("{}) {}", i + 1, choice)).collect();
 	loop {
-		print!("How would you report the value of {}: ", name);
+		let mut data = String::new();
+		println!("{}", picks.join("  "));
+		match &default {
+			Some(val) => print!("Which {} applies [{}]: ", field.name, val),
+			None => print!("Which {} applies: ", field.name),
+		}
 		io::Write::flush(&mut io::stdout()).expect("flush failed!");
 		io::stdin().read_line(&mut data).expect("Invalid input received");
-		// if f64::from(data)
-		if data.trim().len() == 0 {
+		if data.trim().is_empty() {
+			return default;
+		}
+		if data.trim() == CLEAR {
 			return None;
 		}
-		let num = data.trim().parse::<f64>();
-		if num.is_ok() {
-			return Some(num.unwrap())
-		} else {
-			println!("\nData given was not a valid number.")
+		match field.meta.find_choice(&data) {
+			Some(choice) => return Some(String::from(choice)),
+			None => println!("\nPlease pick one of the listed choices by name or number."),
+		}
+	}
+}
+
+fn toggle_state(config: &Config, state: &Option<String>, date: &Option<String>, amount: Option<f64>) {
+	let today = time::OffsetDateTime::now_utc().date();
+	let on = match date.as_deref().map(parse_date) {
+		Some(Ok(date)) => date,
+		Some(Err(e)) => {
+			println!("{}", e);
+			return
+		},
+		None => today,
+	};
+	let dbh = DbHandler::new(config.db_path());
+
+	let name = match state {
+		Some(name) => name.trim().replace(' ', "_"),
+		None if amount.is_some() => {
+			println!("--amount needs a state to apply to, as in `mood toggle medication --amount 20`.");
+			return
+		},
+		None => {
+			list_states(&dbh, today, date.as_ref().map(|_| on));
+			return
+		},
+	};
+	match dbh.toggle_state(&name, amount, on) {
+		Ok(StateChange::Opened) => println!("State {} started on {}.", name, on),
+		Ok(StateChange::Closed) => println!("State {} ended on {}.", name, on),
+		Ok(StateChange::AmountChanged) => println!("Amount for state {} changed to {} on {}.", name, amount.unwrap(), on),
+		Err(e) => println!("Error occurred: {:#}", e),
+	}
+}
+
+fn list_states(dbh: &DbHandler, today: Date, active_on: Option<Date>) {
+	let states = match active_on {
+		Some(date) => dbh.get_active_states(date),
+		None => dbh.get_states(),
+	};
+	let states = match states {
+		Ok(states) => states,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	if states.is_empty() {
+		println!("No states recorded. Use `mood toggle <state>` to start one.");
+		return
+	}
+
+	let describe = |state: &State| {
+		let amount = state.amount.map(|a| format!(" ({})", a)).unwrap_or_default();
+		match state.end {
+			Some(end) => format!("  {}{}: {} to {} ({} days)", state.name, amount, state.start, end, state.duration(today)),
+			None => format!("  {}{}: since {} ({} days)", state.name, amount, state.start, state.duration(today)),
 		}
+	};
+	if let Some(date) = active_on {
+		println!("States active on {}:", date);
+		states.iter().for_each(|s| println!("{}", describe(s)));
+		return
+	}
+
+	let (open, closed): (Vec<&State>, Vec<&State>) = states.iter().partition(|s| s.is_open());
+	if !open.is_empty() {
+		println!("Open states:");
+		open.iter().for_each(|s| println!("{}", describe(s)));
+	}
+	if !closed.is_empty() {
+		println!("Closed states:");
+		closed.iter().for_each(|s| println!("{}", describe(s)));
 	}
 }
 
-fn analyze_db() {
+fn analyze_db(config: &Config) {
 	println!("Performing analysis...");
-	analyze(PathBuf::from("not a real path"));
+	let report = match analyze(config) {
+		Ok(report) => report,
+		Err(e) => {
+			println!("Error occurred: {:#}", e);
+			return
+		},
+	};
+	report.print();
+
+	let dbh = DbHandler::new(config.db_path());
+	match dbh.upsert_recommendations(&report.recommendations(), config.min_confidence) {
+		Ok(0) => (),
+		Ok(saved) => println!("\nSaved {} findings; see them with `mood recommendations`.", saved),
+		Err(e) => println!("Error occurred: {}", e),
+	}
+}
+
+fn recommendations(config: &Config, hide: Option<i64>, all: bool) {
+	let dbh = DbHandler::new(config.db_path());
+	if let Some(id) = hide {
+		match dbh.hide_recommendation(id) {
+			Ok(true) => println!("Hid recommendation {}.", id),
+			Ok(false) => println!("There is no recommendation with id {}.", id),
+			Err(e) => println!("Error occurred: {}", e),
+		}
+		return
+	}
+
+	let recommendations = match dbh.get_recommendations(all) {
+		Ok(recommendations) => recommendations,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	if recommendations.is_empty() {
+		println!("No recommendations yet; run `mood analyze` to look for some.");
+		return
+	}
+
+	for r in recommendations {
+		let input = match r.lag {
+			Some(lag) => format!("{}+{}d", r.input, lag),
+			None => r.input.clone(),
+		};
+		println!(
+			"{:>4}  {:>5.1}%  {:11}  {} -> {}{}{}",
+			r.id,
+			r.confidence * 100.0,
+			r.kind.as_str(),
+			input,
+			r.output,
+			if r.stale { "  (stale)" } else { "" },
+			if r.hidden { "  (hidden)" } else { "" },
+		);
+	}
+}
+
+fn search_notes(config: &Config, query: &str) {
+	let dbh = DbHandler::new(config.db_path());
+	match dbh.search_notes(query) {
+		Ok(matches) if matches.is_empty() => println!("No notes match {}.", query),
+		Ok(matches) => {
+			for (date, snippet) in matches {
+				println!("{}  {}", date, snippet.replace('\n', " "));
+			}
+		},
+		Err(e) => println!("Error occurred: {}", e),
+	}
+}
+
+/// Correlates every pair of numeric fields, with entries readied as they
+/// are for `analyze` so that times of day wrap around midnight.
+fn correlate(dbh: &DbHandler, max_lag: u32) -> Result<Vec<Correlation>> {
+	let (fields, entries) = prepare_entries(&dbh.get_all_fields()?, dbh.get_entries()?);
+	Ok(correlation_matrix(&fields, &entries, max_lag))
+}
+
+fn correlate_db(config: &Config, lags: Option<u32>, output: &Option<String>) {
+	let dbh = DbHandler::new(config.db_path());
+	let correlations = match correlate(&dbh, lags.unwrap_or(config.coincidence_window)) {
+		Ok(correlations) => correlations,
+		Err(e) => {
+			println!("Error occurred: {}", e);
+			return
+		},
+	};
+	match output {
+		Some(path) => match export_matrix(&correlations, Path::new(path)) {
+			Ok(_) => println!("Wrote {} correlations to {}.", correlations.len(), path),
+			Err(e) => println!("Error occurred: {:#}", e),
+		},
+		None => print_matrix(&correlations),
+	}
 }
 
 #[cfg(test)]
@@ -316,9 +1544,77 @@ mod main_tests {
 				(String::from("calories"), 1700.0),
 			]),
 			boolean_fields: HashMap::from([(String::from("exercise"), true)]),
+			categorical_fields: HashMap::new(),
 			tags: vec![String::from("ate:gluten"), String::from("spoke:diego")],
+			note: None,
 		};
 
 		assert!(entries.pop().unwrap() == entry)
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn log_values() {
+		let field = |name: &str, data_type| Field {
+			name: String::from(name),
+			category: FieldCategory::Input,
+			data_type,
+			active: true,
+			meta: FieldMeta::default(),
+		};
+		let fields = vec![
+			Field { meta: FieldMeta { min: Some(1.0), max: Some(10.0), ..FieldMeta::default() }, ..field("mood", FieldType::Numeric) },
+			field("exercise", FieldType::Boolean),
+			field("sleep", FieldType::Duration),
+			Field { meta: FieldMeta { choices: vec![String::from("run"), String::from("yoga")], ..FieldMeta::default() }, ..field("workout", FieldType::Categorical) },
+			field("tags", FieldType::Text),
+		];
+		let date = Date::from_julian_day(22124).unwrap();
+		let args = |values: &[&str]| values.iter().map(|v| String::from(*v)).collect::<Vec<String>>();
+
+		let entry = parse_log_values(&fields, date, &args(&["mood=7", "exercise=y", "sleep=7h20m", "workout=Yoga"])).unwrap();
+		assert!(entry.numeric_fields == HashMap::from([(String::from("mood"), 7.0), (String::from("sleep"), 440.0)]));
+		assert!(entry.boolean_fields.get("exercise") == Some(&true));
+		assert!(entry.categorical_fields.get("workout") == Some(&String::from("yoga")));
+
+		let problems = parse_log_values(&fields, date, &args(&["mood=11", "exercise=maybe", "energy=3", "mood", "workout=swim", "tags=ate:rice", "sleep=long"])).unwrap_err();
+		assert!(problems == vec![
+			"mood: Answers must be between 1 and 10.",
+			"exercise: expected y or n, not maybe.",
+			"There is no active field named energy.",
+			"mood is not of the form name=value.",
+			"workout: expected one of run, yoga, not swim.",
+			"Tags are given with --tags.",
+			"sleep: Data given was not a valid duration, such as 7h20m, 45m or 1:30.",
+		]);
+		assert!(parse_log_values(&fields, date, &args(&["mood=7", "mood=8"])).unwrap_err() == vec!["mood is given more than once."]);
+	}
+
+	#[test]
+	fn correlate_bedtimes() {
+		let dbh = setup_db("test_correlate.db");
+		for (name, category, data_type) in [("bedtime", FieldCategory::Input, FieldType::TimeOfDay), ("mood", FieldCategory::Output, FieldType::Numeric)] {
+			dbh.insert_field(&Field { name: String::from(name), category, data_type, active: true, meta: FieldMeta::default() }).unwrap();
+		}
+		// Bedtimes either side of midnight; each ten minutes later costs half
+		// a point of mood the same day.
+		let start = Date::from_calendar_date(2022, time::Month::May, 1).unwrap();
+		for day in 0..24 {
+			let minutes = (day % 8) as f64 * 10.0 - 30.0;
+			dbh.insert_entry(&Entry {
+				date: start + time::Duration::days(day),
+				numeric_fields: HashMap::from([
+					(String::from("bedtime"), (minutes + 1440.0) % 1440.0),
+					(String::from("mood"), 6.0 - minutes / 20.0),
+				]),
+				boolean_fields: HashMap::new(),
+				categorical_fields: HashMap::new(),
+				tags: Vec::new(),
+				note: None,
+			}).unwrap();
+		}
+
+		let correlations = correlate(&dbh, 0).unwrap();
+		assert!(correlations.len() == 1);
+		assert!(correlations[0].pearson < -0.99);
+	}
+}
@@ -1,4 +1,8 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use time::Date;
 
 #[derive(PartialEq, Clone, Debug)]
@@ -7,14 +11,249 @@ pub struct Field {
     pub category: FieldCategory,
     pub data_type: FieldType,
     pub active: bool,
+    pub meta: FieldMeta,
 }
 
+/// Optional details of how a numeric field is measured. Answers outside
+/// `min` and `max`, or off the `step`, are refused at the entry prompt.
+/// Categorical fields keep their list of answers here instead.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct FieldMeta {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Such as "hours" or "kcal".
+    pub unit: Option<String>,
+    /// Granularity of answers, counted from `min` (or zero), such as 0.5
+    /// for half points.
+    pub step: Option<f64>,
+    /// What the values mean, such as "1 = awful, 10 = great".
+    pub description: Option<String>,
+    /// The answers a categorical field accepts, in the order offered.
+    pub choices: Vec<String>,
+}
+
+impl FieldMeta {
+    /// Checks that the scale makes sense: the range is not reversed and the
+    /// step is positive.
+    pub fn check(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(String::from("The lowest valid value cannot be above the highest."))
+            }
+        }
+        if let Some(step) = self.step {
+            if step <= 0.0 || !step.is_finite() {
+                return Err(String::from("The step between valid values must be above zero."))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks an answer against the range and step, explaining any problem.
+    pub fn validate(&self, value: f64) -> Result<(), String> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if value < min || value > max => {
+                return Err(format!("Answers must be between {} and {}.", self.format(min), self.format(max)))
+            },
+            (Some(min), _) if value < min => return Err(format!("Answers must be at least {}.", self.format(min))),
+            (_, Some(max)) if value > max => return Err(format!("Answers must be at most {}.", self.format(max))),
+            _ => (),
+        }
+        if let Some(step) = self.step.filter(|step| *step > 0.0) {
+            let steps = (value - self.min.unwrap_or(0.0)) / step;
+            if (steps - steps.round()).abs() > 1e-6 {
+                return Err(format!("Answers must be in steps of {}.", step))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Formats a value with its unit, such as "7.5 hours".
+    pub fn format(&self, value: f64) -> String {
+        match &self.unit {
+            Some(unit) => format!("{} {}", value, unit),
+            None => value.to_string(),
+        }
+    }
+
+    /// Summarises the unit and range for labelling prompts and reports,
+    /// such as "hours, 0 to 24", or `None` if nothing is known. Choices are
+    /// listed as "run/lift/yoga".
+    pub fn label(&self) -> Option<String> {
+        let range = match (self.min, self.max) {
+            (Some(min), Some(max)) => Some(format!("{} to {}", min, max)),
+            (Some(min), None) => Some(format!("at least {}", min)),
+            (None, Some(max)) => Some(format!("at most {}", max)),
+            (None, None) => None,
+        };
+        let choices = Some(self.choices.join("/")).filter(|c| !c.is_empty());
+        let parts: Vec<String> = self.unit.iter().cloned().chain(range).chain(choices).collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+
+    /// Finds the choice an answer refers to, either by its number in the
+    /// list (counting from 1) or by its name, ignoring case.
+    pub fn find_choice(&self, answer: &str) -> Option<&str> {
+        let answer = answer.trim();
+        if let Ok(number) = answer.parse::<usize>() {
+            return number.checked_sub(1).and_then(|i| self.choices.get(i)).map(String::as_str)
+        }
+        self.choices.iter().find(|c| c.eq_ignore_ascii_case(answer)).map(String::as_str)
+    }
+}
+
+/// A single day's answers. Fields that were left blank that day are simply
+/// absent from `numeric_fields`, `boolean_fields` and `categorical_fields`.
+/// Counts, durations and times of day are kept with the numeric answers,
+/// as whole numbers, minutes and minutes after midnight respectively.
 #[derive(PartialEq, Clone, Debug)]
 pub struct Entry {
     pub date: Date,
     pub numeric_fields: HashMap<String, f64>,
     pub boolean_fields: HashMap<String, bool>,
+    pub categorical_fields: HashMap<String, String>,
     pub tags: Vec<String>,
+    /// Free text about the day, searchable with `DbHandler::search_notes`.
+    pub note: Option<String>,
+}
+
+/// What to do when an entry is written for a date that already has one.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EntryConflict {
+    /// Keep the existing values, overwriting only those answered anew.
+    Merge,
+    /// Discard the existing entry entirely.
+    Replace,
+    /// Leave the existing entry untouched.
+    Abort,
+}
+
+/// The outcome of writing an entry with `DbHandler::upsert_entry`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EntryWrite {
+    Inserted,
+    Merged,
+    Replaced,
+    Aborted,
+}
+
+/// The outcome of renaming a tag with `DbHandler::rename_tag`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TagRename {
+    Renamed,
+    /// The new name was already in use, so the two tags were combined.
+    Merged,
+}
+
+impl Entry {
+    /// Folds the answers in `other` into this entry. Values present in
+    /// `other` win, including its note, and tags from both entries are kept.
+    pub fn merge(&mut self, other: &Entry) {
+        for (key, value) in &other.numeric_fields {
+            self.numeric_fields.insert(key.clone(), *value);
+        }
+        for (key, value) in &other.boolean_fields {
+            self.boolean_fields.insert(key.clone(), *value);
+        }
+        for (key, value) in &other.categorical_fields {
+            self.categorical_fields.insert(key.clone(), value.clone());
+        }
+        for tag in &other.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        if other.note.is_some() {
+            self.note = other.note.clone();
+        }
+    }
+}
+
+/// A long-running condition, such as a move or a course of medication,
+/// that holds from `start` up to but not including `end`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct State {
+    pub name: String,
+    pub amount: Option<f64>,
+    pub start: Date,
+    /// `None` while the state is still ongoing.
+    pub end: Option<Date>,
+}
+
+impl State {
+    pub fn is_open(&self) -> bool {
+        self.end.is_none()
+    }
+
+    /// Length of the state in days, counting an open state up to `today`.
+    pub fn duration(&self, today: Date) -> i64 {
+        (self.end.unwrap_or(today) - self.start).whole_days()
+    }
+}
+
+/// What `DbHandler::toggle_state` did to a state.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StateChange {
+    Opened,
+    Closed,
+    /// The state stayed open but its amount changed, closing one interval
+    /// and opening another.
+    AmountChanged,
+}
+
+/// A finding from analysis, stored so that it can be reviewed and hidden
+/// across runs. A finding is identified by its type, output and input.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Recommendation {
+    pub id: i64,
+    pub kind: RecommendationType,
+    pub output: String,
+    /// What the output responds to; its meaning depends on `kind`.
+    pub input: String,
+    /// Days between the input and the output, for correlations and tags.
+    pub lag: Option<u32>,
+    pub confidence: f64,
+    pub hidden: bool,
+    /// Whether the latest analysis no longer found this.
+    pub stale: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecommendationType {
+    Trend,
+    Correlation,
+    Boolean,
+    Tag,
+    State,
+}
+
+impl RecommendationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecommendationType::Trend => "trend",
+            RecommendationType::Correlation => "correlation",
+            RecommendationType::Boolean => "boolean",
+            RecommendationType::Tag => "tag",
+            RecommendationType::State => "state",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "trend" => Some(RecommendationType::Trend),
+            "correlation" => Some(RecommendationType::Correlation),
+            "boolean" => Some(RecommendationType::Boolean),
+            "tag" => Some(RecommendationType::Tag),
+            "state" => Some(RecommendationType::State),
+            _ => None,
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Debug, Default)]
@@ -24,26 +263,30 @@ pub struct Trend {
     pub orig_val: f64,
     pub slope: f64,
     pub stddev: f64,
-    //TODO: include # of data points considered?
+    pub points: u32,
 }
 
-// Database location
-// Spike window (default 14)
-// Trend window (default 30)
-// Trend rolling window (default 7)
-// Coincidence window (default 3)
-// Minimum confidence interval (default 0.8)
-// Maximum timeline consideration (default 730)
-
+/// User settings, stored as `config.toml` in the mood config directory.
+/// Any setting missing from the file takes its default value.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    /// Database location
     pub db_path: String,
-    pub spike_window: usize,
-    pub trend_window: usize,
+    /// Days considered when looking for spikes (default 14)
+    pub spike_window: u32,
+    /// Minimum length in days of a reported trend (default 30)
+    pub trend_window: u32,
+    /// Data points used to seed each trend (default 14)
     pub trend_rolling_window: usize,
-    pub coincidence_window: usize,
-    pub min_confidence: usize,
-    pub max_confidence: usize,
-    pub max_timeline: usize,
+    /// Days after an event during which its effects are counted (default 3)
+    pub coincidence_window: u32,
+    /// Minimum confidence before a finding is reported (default 0.8)
+    pub min_confidence: f64,
+    /// Days a tag must appear on before its effects are reported (default 5)
+    pub min_support: u32,
+    /// Maximum timeline consideration in days (default 730)
+    pub max_timeline: u32,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -58,6 +301,144 @@ pub enum FieldType {
     Numeric,
     Boolean,
     Text,
+    /// One of a fixed list of answers, such as run, lift or yoga.
+    Categorical,
+    /// A length of time, such as 7h20m, stored in minutes.
+    Duration,
+    /// A clock time, such as 23:40, stored in minutes after midnight.
+    TimeOfDay,
+    /// A whole number of occurrences.
+    Count,
+}
+
+impl FieldType {
+    /// Whether answers are stored as numbers, and so analysed like numeric
+    /// fields.
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, FieldType::Numeric | FieldType::Duration | FieldType::TimeOfDay | FieldType::Count)
+    }
+
+    /// Reads an answer to a field of this type, such as "7h20m" for a
+    /// duration or "11:40pm" for a time of day.
+    pub fn parse_value(&self, s: &str) -> Option<f64> {
+        let s = s.trim();
+        match self {
+            FieldType::Duration => parse_duration(s),
+            FieldType::TimeOfDay => parse_time_of_day(s),
+            FieldType::Count => s.parse::<u32>().ok().map(f64::from),
+            _ => s.parse().ok(),
+        }
+    }
+
+    /// Writes a stored value back out the way it would be entered.
+    pub fn format_value(&self, value: f64) -> String {
+        match self {
+            FieldType::Duration => format_duration(value),
+            FieldType::TimeOfDay => format_time_of_day(value),
+            _ => value.to_string(),
+        }
+    }
+}
+
+/// Parses a duration into minutes. Hours and minutes may be given as
+/// "7h20m", "7.5h" or "45 min", or as "7:20"; a bare number is minutes.
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let s = s.trim().to_lowercase();
+    if let Ok(minutes) = s.parse::<f64>() {
+        return Some(minutes).filter(|m| *m >= 0.0).map(f64::round)
+    }
+    if let Some((hours, minutes)) = s.split_once(':') {
+        let hours: u32 = hours.parse().ok()?;
+        let minutes: u32 = minutes.parse().ok().filter(|m| *m < 60)?;
+        return hours.checked_mul(60)?.checked_add(minutes).map(f64::from)
+    }
+
+    let mut total = 0.0;
+    let mut rest = s.as_str();
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = rest[number_end..].trim_start();
+        let unit_end = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        total += number * match &rest[..unit_end] {
+            "h" | "hr" | "hrs" | "hour" | "hours" => 60.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 1.0,
+            _ => return None,
+        };
+        rest = rest[unit_end..].trim_start();
+    }
+
+    Some(total.round()).filter(|_| !s.is_empty())
+}
+
+/// Parses a clock time such as "23:40", "7:05am" or "11pm" into minutes
+/// after midnight.
+pub fn parse_time_of_day(s: &str) -> Option<f64> {
+    let s = s.trim().to_lowercase();
+    let (clock, offset) = match (s.strip_suffix("am"), s.strip_suffix("pm")) {
+        (Some(clock), _) => (clock.trim_end(), Some(0)),
+        (_, Some(clock)) => (clock.trim_end(), Some(12)),
+        _ => (s.as_str(), None),
+    };
+    let (hours, minutes) = match clock.split_once(':') {
+        Some((hours, minutes)) if minutes.len() == 2 => (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?),
+        Some(_) => return None,
+        None if offset.is_some() => (clock.parse().ok()?, 0),
+        None => return None,
+    };
+    let hours = match offset {
+        Some(offset) if (1..=12).contains(&hours) => hours % 12 + offset,
+        Some(_) => return None,
+        None => hours,
+    };
+    if hours > 23 || minutes > 59 {
+        return None
+    }
+
+    Some(f64::from(hours * 60 + minutes))
+}
+
+/// Formats minutes as a duration, such as "7h20m".
+pub fn format_duration(minutes: f64) -> String {
+    let minutes = minutes.round() as i64;
+    let sign = if minutes < 0 { "-" } else { "" };
+    match (minutes.abs() / 60, minutes.abs() % 60) {
+        (0, m) => format!("{}{}m", sign, m),
+        (h, 0) => format!("{}{}h", sign, h),
+        (h, m) => format!("{}{}h{}m", sign, h, m),
+    }
+}
+
+/// Formats minutes after midnight as a clock time, such as "23:40". Values
+/// outside a single day wrap around.
+pub fn format_time_of_day(minutes: f64) -> String {
+    let minutes = (minutes.round() as i64).rem_euclid(MINUTES_PER_DAY as i64);
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+pub const MINUTES_PER_DAY: f64 = 1440.0;
+
+/// The circular mean of some times of day, so that the average of 23:50 and
+/// 00:10 is midnight rather than noon. Falls back to noon when there are no
+/// times or they are spread evenly around the clock.
+pub fn mean_time_of_day(times: &[f64]) -> f64 {
+    let angle = |t: &f64| t / MINUTES_PER_DAY * std::f64::consts::TAU;
+    let sin: f64 = times.iter().map(|t| angle(t).sin()).sum();
+    let cos: f64 = times.iter().map(|t| angle(t).cos()).sum();
+    if sin.hypot(cos) < 1e-9 * times.len().max(1) as f64 {
+        return MINUTES_PER_DAY / 2.0
+    }
+
+    // Rounding to the minute keeps a mean of midnight at 0 rather than just
+    // under a day.
+    (sin.atan2(cos) / std::f64::consts::TAU * MINUTES_PER_DAY).round().rem_euclid(MINUTES_PER_DAY)
+}
+
+/// Moves a time of day by whole days to within twelve hours of `centre`,
+/// so that times either side of midnight can be compared as plain numbers.
+pub fn unwrap_time_of_day(time: f64, centre: f64) -> f64 {
+    let half = MINUTES_PER_DAY / 2.0;
+    centre + (time - centre + half).rem_euclid(MINUTES_PER_DAY) - half
 }
 
 impl Default for Config {
@@ -67,23 +448,232 @@ impl Default for Config {
 		dir.push("mood.db");
 
         Self {
-            db_path: dir.into(),
-            spike_window: Default::default(),
-            trend_window: Default::default(),
-            trend_rolling_window: Default::default(),
-            coincidence_window: Default::default(),
-            min_confidence: Default::default(),
-            max_confidence: Default::default(),
-            max_timeline: Default::default(),
+            db_path: dir.to_string_lossy().into(),
+            spike_window: 14,
+            trend_window: 30,
+            trend_rolling_window: 14,
+            coincidence_window: 3,
+            min_confidence: 0.8,
+            min_support: 5,
+            max_timeline: 730,
+        }
+    }
+}
+
+impl Config {
+    /// The location of `config.toml` in the user's config directory.
+    pub fn default_path() -> PathBuf {
+        let mut path = dirs::config_dir().unwrap();
+        path.push("mood");
+        path.push("config.toml");
+        path
+    }
+
+    /// Reads the config at `path`, falling back to the defaults if no file
+    /// has been created there yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed reading config file {}", path.display()))?;
+        let config: Config = toml::from_str(&contents)
+            .with_context(|| format!("Failed parsing config file {}", path.display()))?;
+        config.validate()
+            .with_context(|| format!("Invalid setting in config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Checks the settings that analysis relies on being in range, naming
+    /// the first one that is not.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // Spike detection fits a line through the first 5/7 of the window,
+        // which needs at least two points.
+        if self.spike_window < 3 {
+            return Err(anyhow::anyhow!("spike_window must be at least 3, not {}", self.spike_window));
+        }
+        if self.trend_rolling_window < 2 {
+            return Err(anyhow::anyhow!("trend_rolling_window must be at least 2, not {}", self.trend_rolling_window));
+        }
+        if !(0.0..=1.0).contains(&self.min_confidence) {
+            return Err(anyhow::anyhow!("min_confidence must be between 0 and 1, not {}", self.min_confidence));
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Failed writing config file {}", path.display()))
+    }
+
+    pub fn db_path(&self) -> PathBuf {
+        PathBuf::from(&self.db_path)
+    }
+}
+
+/// Parses a date given either as an ISO 8601 calendar date (2022-05-12)
+/// or as the literal `today`.
+pub fn parse_date(s: &str) -> anyhow::Result<Date> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("today") {
+        return Ok(time::OffsetDateTime::now_utc().date());
+    }
+
+    let invalid = || format!("Could not parse date '{}'; expected YYYY-MM-DD", s);
+    let parts: Vec<&str> = s.split('-').collect();
+    if parts.len() != 3 {
+        return Err(anyhow::anyhow!(invalid()));
+    }
+    let year: i32 = parts[0].parse().with_context(invalid)?;
+    let month: u8 = parts[1].parse().with_context(invalid)?;
+    let day: u8 = parts[2].parse().with_context(invalid)?;
+    let month = time::Month::try_from(month).with_context(invalid)?;
+
+    Date::from_calendar_date(year, month, day).with_context(invalid)
+}
+
+/// Reads a yes or no answer given as y/n, yes/no, true/false or 1/0.
+pub fn parse_boolean(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "y" | "yes" | "true" | "1" => Some(true),
+        "n" | "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Checks a single choice for a categorical field. Choices are picked by
+/// number at the prompt and stored one per line, so they can be neither
+/// numbers nor span lines.
+pub fn check_choice(choice: &str) -> Result<(), String> {
+    if choice.parse::<f64>().is_ok() {
+        return Err(format!("{} is a number; choices must be words.", choice))
+    }
+    if choice.contains(['\n', '\r']) {
+        return Err(format!("{:?} spans more than one line.", choice))
+    }
+
+    Ok(())
+}
+
+/// Checks every choice of a categorical field, refusing lists with fewer
+/// than two distinct entries.
+pub fn check_choices(choices: &[String]) -> Result<(), String> {
+    for (i, choice) in choices.iter().enumerate() {
+        check_choice(choice)?;
+        if choices[..i].iter().any(|c| c.eq_ignore_ascii_case(choice)) {
+            return Err(format!("{} is listed more than once.", choice))
+        }
+    }
+    if choices.len() < 2 {
+        return Err(String::from("At least two choices are needed."))
+    }
+
+    Ok(())
+}
+
+/// Splits a comma-separated list of choices and checks them as
+/// `check_choices` does.
+pub fn parse_choices(list: &str) -> Result<Vec<String>, String> {
+    let choices: Vec<String> = list.split(',').map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect();
+    check_choices(&choices)?;
+
+    Ok(choices)
+}
+
+/// Returns the namespaces of a `namespace:value` tag, outermost first.
+/// Namespaces nest, so `ate:japanese:sushi` is in both `ate` and
+/// `ate:japanese`. A tag without a namespace, or with an empty part such as
+/// `ate:`, is in none.
+pub fn tag_namespaces(tag: &str) -> Vec<&str> {
+    if !tag.contains(':') || tag.split(':').any(|part| part.trim().is_empty()) {
+        return Vec::new()
+    }
+
+    tag.match_indices(':').map(|(i, _)| &tag[..i]).collect()
+}
+
+/// Returns the outermost namespace of `tag` if none of `namespaces` is
+/// it, as is likely for a typo such as `aet:pizza`.
+pub fn unknown_namespace<'a>(tag: &'a str, namespaces: &[String]) -> Option<&'a str> {
+    let outermost = *tag_namespaces(tag).first()?;
+    if namespaces.iter().any(|n| n == outermost) {
+        None
+    } else {
+        Some(outermost)
+    }
+}
+
+/// Counts the single-character insertions, deletions, substitutions and
+/// swaps of neighbouring characters needed to turn `a` into `b`, so that
+/// `deigo` is one edit away from `diego`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
         }
     }
+
+    distances[a.len()][b.len()]
+}
+
+/// Returns the known tag that `tag` is most likely a misspelling of, if it
+/// is not itself known. Tags whose value (the part after any namespace) is
+/// short may be one edit away, longer ones two, so that `spoke:anna` is not
+/// mistaken for `spoke:dina`. Ties go to the tag listed first.
+pub fn closest_tag<'a>(tag: &str, known: &'a [String]) -> Option<&'a str> {
+    if known.iter().any(|k| k == tag) {
+        return None
+    }
+    let value = tag.rsplit(':').next().unwrap_or(tag);
+    let limit = if value.chars().count() <= 4 { 1 } else { 2 };
+
+    known
+        .iter()
+        .map(|k| (edit_distance(tag, k), k))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, k)| k.as_str())
+}
+
+/// Returns the known tags that start with `prefix`.
+pub fn complete_tag<'a>(prefix: &str, known: &'a [String]) -> Vec<&'a str> {
+    known.iter().filter(|k| k.starts_with(prefix)).map(|k| k.as_str()).collect()
+}
+
+/// Formats a Julian day number, as stored in the database, as an ISO date.
+pub fn format_julian(day: u32) -> String {
+    match Date::from_julian_day(day as i32) {
+        Ok(date) => date.to_string(),
+        Err(_) => day.to_string(),
+    }
 }
 
 #[cfg(test)]
 pub mod test_utils {
     use crate::prelude::*;
-    use csv::Reader;
     use std::fs;
+    use std::path::PathBuf;
 
     pub fn setup_db(file: &str) -> DbHandler {
         let path = PathBuf::from(file);
@@ -91,94 +681,155 @@ pub mod test_utils {
             let _ = fs::remove_file(&path);
         }
 
-        let dbh = DbHandler::initialize_db(PathBuf::from(path)).unwrap();
-
-        dbh
+        DbHandler::initialize_db(path).unwrap()
     }
 
     pub fn import_csv(dbh: &DbHandler, file: &str) {
-        let mut reader = Reader::from_path(file).unwrap();
-        let headers = reader.headers().unwrap();
-        let mut header_cols = Vec::new();
-        let mut fields = Vec::new();
-        for record in headers {
-            header_cols.push(String::from(&record[2..]));
-            if record == "date" || record == "tags" {
-                continue;
-            }
-            let category = match &record[0..1] {
-                "I" => FieldCategory::Input,
-                "O" => FieldCategory::Output,
-                _ => FieldCategory::Hybrid,
-            };
-            let data_type = match &record[1..2] {
-                "N" => FieldType::Numeric,
-                "B" => FieldType::Boolean,
-                _ => FieldType::Text,
-            };
-
-            let field = Field {
-                name: String::from(&record[2..]),
-                category,
-                data_type,
-                active: true,
-            };
-            let _ = dbh.insert_field(&field);
-            fields.push(field);
-        }
-
-        let field = Field {
-            name: String::from("tags"),
-            category: FieldCategory::Input,
-            data_type: FieldType::Text,
-            active: true,
+        let fields = dbh.get_all_fields().unwrap();
+        let import = read_csv(&PathBuf::from(file), &fields).unwrap();
+        assert!(import.errors.is_empty(), "rows not imported: {:?}", import.errors);
+        dbh.import_entries(&import.fields, &import.entries).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod util_tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trip() {
+        let path = PathBuf::from("test_config.toml");
+        let _ = fs::remove_file(&path);
+        assert!(Config::load(&path).unwrap() == Config::default());
+
+        let config = Config {
+            db_path: String::from("experiment.db"),
+            spike_window: 21,
+            min_confidence: 0.9,
+            ..Config::default()
         };
-        // let _ = dbh.insert_field(&field);
-        fields.push(field);
-        // println!("{:#?}", fields);
-
-        for record in reader.records() {
-            // println!("Beginning row parsing");
-            let record = record.unwrap();
-            let mut date = None;
-            let mut i = 0;
-            let mut numeric_fields = HashMap::new();
-            let mut boolean_fields = HashMap::new();
-            let mut tags = Vec::new();
-
-            for a in &record {
-                // println!("{}", a);
-                if i == 0 {
-                    let n: i32 = a.parse().unwrap();
-                    date = Some(Date::from_julian_day(n).unwrap());
-                } else {
-                    let field = fields.get(i - 1).unwrap();
-                    match field.data_type {
-                        FieldType::Numeric => {
-                            numeric_fields.insert(field.name.clone(), a.parse().unwrap());
-                        }
-                        FieldType::Boolean => {
-                            boolean_fields.insert(field.name.clone(), a.parse().unwrap());
-                        }
-                        FieldType::Text => {
-                            tags = a.split(" ").map(|s| String::from(s)).collect();
-                        }
-                    }
-                }
-                // record.unwrap()
-                i += 1;
-            }
+        config.save(&path).unwrap();
+        assert!(Config::load(&path).unwrap() == config);
 
-            let entry = Entry {
-                date: date.unwrap(),
-                numeric_fields,
-                boolean_fields,
-                tags,
-            };
-            let a = dbh.insert_entry(&entry);
-            if let Err(message) = a {
-                println!("Error occurred: {}", message);
-            }
+        fs::write(&path, "trend_window = 45\n").unwrap();
+        let partial = Config::load(&path).unwrap();
+        assert!(partial.trend_window == 45);
+        assert!(partial.spike_window == Config::default().spike_window);
+
+        fs::write(&path, "trend_window = \"long\"\n").unwrap();
+        assert!(Config::load(&path).is_err());
+        for setting in ["trend_rolling_window = 0", "spike_window = 1", "min_confidence = 1.5"] {
+            fs::write(&path, format!("{}\n", setting)).unwrap();
+            let message = format!("{:#}", Config::load(&path).unwrap_err());
+            assert!(message.contains(setting.split(' ').next().unwrap()), "{}", message);
         }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn namespaces() {
+        assert!(tag_namespaces("ate:gluten") == vec!["ate"]);
+        assert!(tag_namespaces("ate:japanese:sushi") == vec!["ate", "ate:japanese"]);
+        assert!(tag_namespaces("headache").is_empty());
+        assert!(tag_namespaces("ate:").is_empty());
+        assert!(tag_namespaces(":gluten").is_empty());
+
+        let known = vec![String::from("ate"), String::from("spoke")];
+        assert!(unknown_namespace("ate:japanese:sushi", &known).is_none());
+        assert!(unknown_namespace("headache", &known).is_none());
+        assert!(unknown_namespace("aet:pizza", &known) == Some("aet"));
+    }
+
+    #[test]
+    fn tag_suggestions() {
+        assert!(edit_distance("diego", "diego") == 0);
+        assert!(edit_distance("deigo", "diego") == 1);
+        assert!(edit_distance("dieg", "diego") == 1);
+        assert!(edit_distance("kitten", "sitting") == 3);
+        assert!(edit_distance("", "ate") == 3);
+
+        let known = vec![String::from("spoke:diego"), String::from("spoke:dina"), String::from("ate:rice"), String::from("gym")];
+        assert!(closest_tag("spoke:deigo", &known) == Some("spoke:diego"));
+        assert!(closest_tag("spoke:diego", &known).is_none());
+        assert!(closest_tag("ate:ice", &known) == Some("ate:rice"));
+        assert!(closest_tag("gum", &known) == Some("gym"));
+        assert!(closest_tag("run", &known).is_none());
+        assert!(closest_tag("spoke:anna", &known).is_none());
+
+        assert!(complete_tag("spoke:d", &known) == vec!["spoke:diego", "spoke:dina"]);
+        assert!(complete_tag("ate:", &known) == vec!["ate:rice"]);
+        assert!(complete_tag("walk:", &known).is_empty());
+    }
+
+    #[test]
+    fn field_meta() {
+        let mood = FieldMeta { min: Some(1.0), max: Some(10.0), step: Some(0.5), ..FieldMeta::default() };
+        assert!(mood.validate(7.5).is_ok());
+        assert!(mood.validate(-40.0).is_err());
+        assert!(mood.validate(11.0).is_err());
+        assert!(mood.validate(7.25).is_err());
+        assert!(mood.label() == Some(String::from("1 to 10")));
+        assert!(mood.check().is_ok());
+        assert!(FieldMeta { min: Some(10.0), max: Some(1.0), ..FieldMeta::default() }.check().is_err());
+        assert!(FieldMeta { step: Some(0.0), ..FieldMeta::default() }.check().is_err());
+        assert!(FieldMeta { step: Some(-0.5), ..FieldMeta::default() }.check().is_err());
+
+        let sleep = FieldMeta { min: Some(0.0), unit: Some(String::from("hours")), ..FieldMeta::default() };
+        assert!(sleep.validate(30.0).is_ok());
+        assert!(sleep.validate(-1.0) == Err(String::from("Answers must be at least 0 hours.")));
+        assert!(sleep.format(7.5) == "7.5 hours");
+        assert!(sleep.label() == Some(String::from("hours, at least 0")));
+        assert!(FieldMeta::default().label().is_none());
+    }
+
+    #[test]
+    fn field_types() {
+        assert!(parse_duration("7h20m") == Some(440.0));
+        assert!(parse_duration("7.5 hours") == Some(450.0));
+        assert!(parse_duration("45min") == Some(45.0));
+        assert!(parse_duration("1:30") == Some(90.0));
+        assert!(parse_duration("90") == Some(90.0));
+        assert!(parse_duration("7x").is_none());
+        assert!(parse_duration("99999999:00").is_none());
+        assert!(parse_duration("").is_none());
+        assert!(format_duration(440.0) == "7h20m");
+        assert!(format_duration(45.0) == "45m");
+        assert!(format_duration(480.0) == "8h");
+
+        assert!(parse_time_of_day("23:40") == Some(1420.0));
+        assert!(parse_time_of_day("7:05am") == Some(425.0));
+        assert!(parse_time_of_day("12am") == Some(0.0));
+        assert!(parse_time_of_day("11 pm") == Some(1380.0));
+        assert!(parse_time_of_day("24:00").is_none());
+        assert!(parse_time_of_day("13pm").is_none());
+        assert!(parse_time_of_day("7").is_none());
+        assert!(format_time_of_day(1420.0) == "23:40");
+        assert!(format_time_of_day(-20.0) == "23:40");
+
+        assert!(FieldType::Count.parse_value("3") == Some(3.0));
+        assert!(FieldType::Count.parse_value("2.5").is_none());
+        assert!(FieldType::Count.parse_value("-1").is_none());
+        assert!(FieldType::TimeOfDay.format_value(1420.0) == "23:40");
+
+        // The mean of 23:40 and 00:20 is midnight, not noon.
+        let centre = mean_time_of_day(&[1420.0, 20.0]);
+        assert!(centre == 0.0);
+        assert!(unwrap_time_of_day(1420.0, centre) == -20.0);
+        assert!(unwrap_time_of_day(20.0, centre) == 20.0);
+        assert!(mean_time_of_day(&[1380.0, 1420.0]) == 1400.0);
+        assert!(mean_time_of_day(&[]) == 720.0);
+
+        assert!(parse_choices("run, lift ,,yoga") == Ok(vec![String::from("run"), String::from("lift"), String::from("yoga")]));
+        assert!(parse_choices("run, Run").is_err());
+        assert!(parse_choices("run, 2").is_err());
+        assert!(parse_choices("run").is_err());
+        assert!(check_choices(&[String::from("run"), String::from("lift\nyoga")]).is_err());
+
+        let workout = FieldMeta { choices: vec![String::from("run"), String::from("lift")], ..FieldMeta::default() };
+        assert!(workout.find_choice("2") == Some("lift"));
+        assert!(workout.find_choice("Run") == Some("run"));
+        assert!(workout.find_choice("3").is_none());
+        assert!(workout.find_choice("0").is_none());
+        assert!(workout.label() == Some(String::from("run/lift")));
     }
 }